@@ -0,0 +1,148 @@
+//! Benchmarks of the common ways of reading an archive
+//!
+//! By default these read a generated archive of small articles in one zlib-compressed cluster.
+//! Set `ZIM_BENCH_FILE` to benchmark a real archive instead, which is the only way to compare
+//! codecs (such as XZ with and without the `liblzma` feature):
+//!
+//! ```text
+//! ZIM_BENCH_FILE=wikipedia.zim cargo bench --bench read
+//! ```
+//!
+//! The `archive` group reads every article, so it takes a while on big archives.
+
+extern crate byteorder;
+#[macro_use]
+extern crate criterion;
+extern crate md5;
+extern crate miniz_oxide;
+extern crate zim;
+
+#[allow(dead_code)]
+#[path = "../src/test_zim.rs"]
+mod test_zim;
+
+use criterion::{black_box, Criterion};
+use std::path::PathBuf;
+use test_zim::{Content, TestEntry};
+use zim::{DirectoryEntry, Target, Zim, ZimOptions};
+
+/// How many articles the generated archive has
+const ARTICLES: usize = 2000;
+
+/// How many articles are looked up or read in each iteration of the per-article benchmarks
+const SAMPLES: usize = 100;
+
+/// Returns the path of the archive to benchmark, writing the generated one if needed
+fn archive() -> PathBuf {
+    if let Some(path) = std::env::var_os("ZIM_BENCH_FILE") {
+        return PathBuf::from(path);
+    }
+    let entries = (0..ARTICLES).map(|i| {
+        let url: &'static str = Box::leak(format!("Article{:04}", i).into_boxed_str());
+        let text = format!("<p>{}</p>", "Some article text. ".repeat(20 + i % 80));
+        let data: &'static [u8] = Box::leak(text.into_bytes().into_boxed_slice());
+        TestEntry { namespace: 'A', url: url, title: url, revision: 0, content: Content::Data("text/html", data) }
+    }).collect();
+    test_zim::write_zim_compressed("bench", entries, None, &|cluster| {
+        (2, miniz_oxide::deflate::compress_to_vec_zlib(cluster, 6))
+    })
+}
+
+/// Returns up to `SAMPLES` articles with data, spread out over the whole archive
+fn sample_articles(zim: &Zim) -> Vec<DirectoryEntry> {
+    let step = std::cmp::max(1, zim.article_count as usize / SAMPLES);
+    zim.iterate_by_urls()
+        .filter(|entry| matches!(entry.target, Some(Target::Cluster(..))))
+        .step_by(step)
+        .take(SAMPLES)
+        .collect()
+}
+
+/// Looking up directory entries, by url and by index
+fn bench_entries(c: &mut Criterion) {
+    let path = archive();
+    let zim = Zim::new(&path).ok().unwrap();
+    let articles = sample_articles(&zim);
+    let step = std::cmp::max(1, zim.article_count as usize / SAMPLES);
+    let indices: Vec<u32> = (0..zim.article_count).step_by(step).collect();
+
+    let mut group = c.benchmark_group("entries");
+    group.bench_function("open", |b| b.iter(|| Zim::new(&path).ok().unwrap()));
+    group.bench_function("get_by_url", |b| b.iter(|| {
+        for entry in &articles {
+            black_box(zim.get_by_url(entry.namespace, &entry.url));
+        }
+    }));
+    for &size in &[0, 256] {
+        let zim = Zim::open_with(&path, ZimOptions::new().entry_cache_size(size)).ok().unwrap();
+        group.bench_function(format!("get_by_url_index/cache {}", size), |b| b.iter(|| {
+            for &idx in &indices {
+                black_box(zim.get_by_url_index(idx));
+            }
+        }));
+    }
+    group.bench_function("entry_info", |b| b.iter(|| {
+        for &idx in &indices {
+            black_box(zim.entry_info(idx));
+        }
+    }));
+    group.finish();
+}
+
+/// Reading single articles' data, with the cluster cache turned off so every read decompresses
+fn bench_blobs(c: &mut Criterion) {
+    let zim = Zim::open_with(archive(), ZimOptions::new().cluster_cache_size(0)).ok().unwrap();
+    let targets: Vec<(u32, u32)> = sample_articles(&zim).iter()
+        .filter_map(|entry| match entry.target {
+            Some(Target::Cluster(cid, bid)) => Some((cid, bid)),
+            _ => None
+        })
+        .collect();
+
+    let mut group = c.benchmark_group("blobs");
+    group.bench_function("get_cluster", |b| b.iter(|| {
+        for &(cid, bid) in &targets {
+            black_box(zim.get_cluster(cid).unwrap().get_blob(bid).len());
+        }
+    }));
+    group.bench_function("decompress_cluster_into", |b| {
+        let mut buf = Vec::new();
+        b.iter(|| {
+            for &(cid, bid) in &targets {
+                black_box(zim.decompress_cluster_into(cid, &mut buf).ok().unwrap().get_blob(bid).len());
+            }
+        })
+    });
+    group.bench_function("read_blob_partial", |b| b.iter(|| {
+        for &(cid, bid) in &targets {
+            black_box(zim.read_blob_partial(cid, bid).ok().unwrap().len());
+        }
+    }));
+    group.finish();
+}
+
+/// Reading every article in the archive
+fn bench_archive(c: &mut Criterion) {
+    let zim = Zim::new(archive()).ok().unwrap();
+
+    let mut group = c.benchmark_group("archive");
+    group.sample_size(10);
+    group.bench_function("read_blob in url order", |b| b.iter(|| {
+        let mut total = 0;
+        for entry in zim.iterate_by_urls() {
+            if let Ok(blob) = zim.read_blob(&entry) {
+                total += blob.data.len();
+            }
+        }
+        total
+    }));
+    group.bench_function("for_each_article_in_cluster_order", |b| b.iter(|| {
+        let mut total = 0;
+        zim.for_each_article_in_cluster_order(|_, data| total += data.len()).ok().unwrap();
+        total
+    }));
+    group.finish();
+}
+
+criterion_group!(benches, bench_entries, bench_blobs, bench_archive);
+criterion_main!(benches);
@@ -0,0 +1,34 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate zim;
+
+use std::io::Write;
+use zim::Zim;
+
+// Run with `cargo fuzz run open_checked`.  Zim only opens files, so each input is written to a
+// temporary file first.
+fuzz_target!(|data: &[u8]| {
+    let path = std::env::temp_dir().join(format!("zim-fuzz-{}.zim", std::process::id()));
+    std::fs::File::create(&path).unwrap().write_all(data).unwrap();
+
+    let zim = match Zim::open_checked(&path) {
+        Ok(zim) => zim,
+        Err(_) => return
+    };
+    for entry in zim.iterate_by_urls() {
+        let _ = zim.read_blob(&entry);
+        let _ = zim.resolve_entry(&entry);
+    }
+    for idx in 0..zim.cluster_count {
+        if let Some(cluster) = zim.get_cluster(idx) {
+            let _ = cluster.get_blob_checked(0);
+        }
+    }
+    for _ in zim.iter_article_content() {}
+    let _ = zim.for_each_article_in_cluster_order(|_, _| {});
+    let _ = zim.size_stats();
+
+    let dir = std::env::temp_dir().join(format!("zim-fuzz-{}-extract", std::process::id()));
+    let _ = zim.extract_to(&dir);
+    let _ = std::fs::remove_dir_all(&dir);
+});
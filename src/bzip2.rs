@@ -0,0 +1,400 @@
+//! A minimal bzip2 decoder, used for clusters written by older ZIM writers.
+//!
+//! Only decompression is supported, and the whole input has to be in memory.  Block CRCs are
+//! checked; the deprecated "randomised" block mode is not supported.
+
+const BLOCK_MAGIC: u64 = 0x314159265359;
+const END_MAGIC: u64 = 0x177245385090;
+const MAX_CODE_LEN: usize = 20;
+const MAX_GROUPS: usize = 6;
+const MAX_SELECTORS: usize = 18002;
+const GROUP_SIZE: usize = 50;
+
+/// Reads bits, most significant first
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> BitReader<'a> {
+        BitReader { data: data, pos: 0, bit: 0 }
+    }
+
+    fn read_bit(&mut self) -> Result<bool, &'static str> {
+        let byte = match self.data.get(self.pos) {
+            Some(&byte) => byte,
+            None => return Err("Unexpected end of bzip2 data")
+        };
+        let bit = (byte >> (7 - self.bit)) & 1 == 1;
+        self.bit += 1;
+        if self.bit == 8 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+        Ok(bit)
+    }
+
+    fn read_bits(&mut self, n: u32) -> Result<u64, &'static str> {
+        let mut value = 0;
+        for _ in 0..n {
+            value = (value << 1) | try!(self.read_bit()) as u64;
+        }
+        Ok(value)
+    }
+
+    /// Skips to the start of the next byte
+    fn align(&mut self) {
+        if self.bit != 0 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+}
+
+/// A canonical Huffman code, as used by bzip2
+struct Huffman {
+    /// The number of codes of each length
+    count: [u32; MAX_CODE_LEN + 1],
+    /// The symbols, sorted by code
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut count = [0; MAX_CODE_LEN + 1];
+        for &len in lengths {
+            count[len as usize] += 1;
+        }
+        let mut symbols = Vec::with_capacity(lengths.len());
+        for len in 1..MAX_CODE_LEN + 1 {
+            for (sym, &l) in lengths.iter().enumerate() {
+                if l as usize == len {
+                    symbols.push(sym as u16);
+                }
+            }
+        }
+        Huffman { count: count, symbols: symbols }
+    }
+
+    fn decode(&self, bits: &mut BitReader) -> Result<u16, &'static str> {
+        // `first` is the first code of the current length, and `index` is the position of its
+        // symbol in `symbols`
+        let mut code = 0;
+        let mut first = 0;
+        let mut index = 0;
+        for len in 1..MAX_CODE_LEN + 1 {
+            code |= try!(bits.read_bit()) as u32;
+            let count = self.count[len];
+            if code < first + count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("Invalid bzip2 Huffman code")
+    }
+}
+
+/// The CRC used by bzip2 (CRC-32 with polynomial 0x04c11db7, most significant bit first)
+fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    for (i, entry) in table.iter_mut().enumerate() {
+        let mut crc = (i as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x80000000 != 0 { (crc << 1) ^ 0x04c11db7 } else { crc << 1 };
+        }
+        *entry = crc;
+    }
+    table
+}
+
+/// Decompresses a bzip2 stream (or several concatenated streams)
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, &'static str> {
+    let mut out = Vec::new();
+    try!(decompress_into(data, &mut out));
+    Ok(out)
+}
+
+/// Decompresses a bzip2 stream (or several concatenated streams) into `out`, which is cleared
+/// first
+pub fn decompress_into(data: &[u8], out: &mut Vec<u8>) -> Result<(), &'static str> {
+    out.clear();
+    let crc_table = crc_table();
+    let mut bits = BitReader::new(data);
+    while !bits.is_empty() {
+        if try!(bits.read_bits(24)) != 0x425a68 {
+            return Err("Bad bzip2 stream header");
+        }
+        let level = try!(bits.read_bits(8));
+        if level < b'1' as u64 || level > b'9' as u64 {
+            return Err("Bad bzip2 block size");
+        }
+        let max_block_len = (level - b'0' as u64) as usize * 100000;
+
+        let mut stream_crc = 0u32;
+        loop {
+            let magic = try!(bits.read_bits(48));
+            if magic == END_MAGIC {
+                if try!(bits.read_bits(32)) as u32 != stream_crc {
+                    return Err("bzip2 stream CRC mismatch");
+                }
+                bits.align();
+                break;
+            }
+            if magic != BLOCK_MAGIC {
+                return Err("Bad bzip2 block header");
+            }
+            let block_crc = try!(bits.read_bits(32)) as u32;
+            let start = out.len();
+            try!(read_block(&mut bits, max_block_len, out));
+
+            let mut crc = 0xffffffffu32;
+            for &b in &out[start..] {
+                crc = (crc << 8) ^ crc_table[((crc >> 24) as u8 ^ b) as usize];
+            }
+            if !crc != block_crc {
+                return Err("bzip2 block CRC mismatch");
+            }
+            stream_crc = stream_crc.rotate_left(1) ^ block_crc;
+        }
+    }
+    Ok(())
+}
+
+/// Decodes a single block, appending its contents to `out`
+fn read_block(bits: &mut BitReader, max_block_len: usize, out: &mut Vec<u8>) -> Result<(), &'static str> {
+    if try!(bits.read_bit()) {
+        return Err("Randomised bzip2 blocks are not supported");
+    }
+    let orig_ptr = try!(bits.read_bits(24)) as usize;
+
+    // which byte values appear in the block
+    let mut used = Vec::new();
+    let groups_used = try!(bits.read_bits(16));
+    for i in 0..16 {
+        if groups_used & (0x8000 >> i) != 0 {
+            let bytes_used = try!(bits.read_bits(16));
+            for j in 0..16 {
+                if bytes_used & (0x8000 >> j) != 0 {
+                    used.push((i * 16 + j) as u8);
+                }
+            }
+        }
+    }
+    if used.is_empty() {
+        return Err("bzip2 block uses no symbols");
+    }
+    let alpha_size = used.len() + 2;
+
+    let num_groups = try!(bits.read_bits(3)) as usize;
+    if !(2..=MAX_GROUPS).contains(&num_groups) {
+        return Err("Bad number of bzip2 Huffman groups");
+    }
+    let num_selectors = try!(bits.read_bits(15)) as usize;
+    if num_selectors == 0 {
+        return Err("Bad number of bzip2 selectors");
+    }
+    let mut mtf_groups: Vec<u8> = (0..num_groups as u8).collect();
+    let mut selectors = Vec::with_capacity(num_selectors);
+    for _ in 0..num_selectors {
+        let mut j = 0;
+        while try!(bits.read_bit()) {
+            j += 1;
+            if j >= num_groups {
+                return Err("Bad bzip2 selector");
+            }
+        }
+        let group = mtf_groups.remove(j);
+        mtf_groups.insert(0, group);
+        // some encoders write more selectors than can be used; the extras are ignored
+        if selectors.len() < MAX_SELECTORS {
+            selectors.push(group);
+        }
+    }
+
+    let mut tables = Vec::with_capacity(num_groups);
+    for _ in 0..num_groups {
+        let mut lengths = vec![0u8; alpha_size];
+        let mut len = try!(bits.read_bits(5)) as i32;
+        for l in lengths.iter_mut() {
+            loop {
+                if len < 1 || len > MAX_CODE_LEN as i32 {
+                    return Err("Bad bzip2 code length");
+                }
+                if !try!(bits.read_bit()) {
+                    break;
+                }
+                len += if try!(bits.read_bit()) { -1 } else { 1 };
+            }
+            *l = len as u8;
+        }
+        tables.push(Huffman::new(&lengths));
+    }
+
+    // undo the Huffman coding, the run-length coding of zeros, and the move-to-front transform
+    let end_of_block = alpha_size as u16 - 1;
+    let mut mtf: Vec<u8> = (0..used.len()).map(|i| i as u8).collect();
+    let mut block: Vec<u32> = Vec::new();
+    let mut counts = [0usize; 256];
+    let mut run = 0;
+    let mut run_weight = 1;
+    let mut decoded = 0;
+    loop {
+        let group = match selectors.get(decoded / GROUP_SIZE) {
+            Some(&group) => group,
+            None => return Err("Ran out of bzip2 selectors")
+        };
+        let sym = try!(tables[group as usize].decode(bits));
+        decoded += 1;
+
+        // RUNA and RUNB encode the length of a run of the front byte in bijective base 2
+        if sym <= 1 {
+            run += run_weight << sym;
+            run_weight <<= 1;
+            if run > max_block_len {
+                return Err("bzip2 block is too long");
+            }
+            continue;
+        }
+        if run > 0 {
+            let b = used[mtf[0] as usize];
+            if block.len() + run > max_block_len {
+                return Err("bzip2 block is too long");
+            }
+            counts[b as usize] += run;
+            block.extend(std::iter::repeat_n(b as u32, run));
+            run = 0;
+            run_weight = 1;
+        }
+        if sym == end_of_block {
+            break;
+        }
+        let idx = mtf.remove(sym as usize - 1);
+        mtf.insert(0, idx);
+        let b = used[idx as usize];
+        if block.len() >= max_block_len {
+            return Err("bzip2 block is too long");
+        }
+        counts[b as usize] += 1;
+        block.push(b as u32);
+    }
+    if orig_ptr >= block.len() {
+        return Err("bzip2 block origin pointer is out of range");
+    }
+
+    // undo the Burrows-Wheeler transform: the low byte of each entry holds the byte, and the rest
+    // holds the position of the next entry
+    let mut next_pos = [0usize; 256];
+    let mut sum = 0;
+    for (b, &count) in counts.iter().enumerate() {
+        next_pos[b] = sum;
+        sum += count;
+    }
+    for i in 0..block.len() {
+        let b = (block[i] & 0xff) as usize;
+        block[next_pos[b]] |= (i as u32) << 8;
+        next_pos[b] += 1;
+    }
+
+    // and finally undo the initial run-length coding, where four equal bytes are followed by a
+    // count of extra repeats
+    let mut pos = block[orig_ptr] >> 8;
+    let mut last = None;
+    let mut same = 0;
+    for _ in 0..block.len() {
+        let entry = block[pos as usize];
+        let b = entry as u8;
+        pos = entry >> 8;
+        if same == 4 {
+            out.extend(std::iter::repeat_n(last.unwrap(), b as usize));
+            same = 0;
+            last = None;
+            continue;
+        }
+        if Some(b) == last {
+            same += 1;
+        } else {
+            same = 1;
+            last = Some(b);
+        }
+        out.push(b);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+const BANANA: &[u8] = b"BZh91AY&SY\xef\xb6\xec\x01\x00\x00\x01\x81\x000\x01 \x000\xcc\x0cz\x88^.\xe4\x8ap\xa1!\xdfm\
+      \xd8\x02";
+
+#[cfg(test)]
+const RUNS: &[u8] = b"BZh91AY&SYh,:\xc4\x00\x00\x01\x81\x01\xb0\x00\x00\x80\x00\x08 \x00!#@\xcd4\xd1BS!\xc5\xdc\
+      \x91N\x14$\x1a\x0b\x0e\xb1\x00";
+
+#[cfg(test)]
+const LINES: &[u8] = b"BZh91AY&SY\x80\x9f\xf4[\x00\x04`Y\x00\x00\x10@\x00\x7f\xe0\x02%P\x03@{\x92\x00\x04U<0\xfdT\
+      \x90\x0a`\x00\x9a\x15O\xf0\xa9\xbdO*\xaad\x0a`\x00\x9a\x09\x14\xcai\xb6\xaah\xd2&;w\xe7y]\
+      \xf0\x80\xd8\x124\x02\x8c\x80[\x00\x050\xd0\x0bd\x02\x8d\x00\x91\xb0 8\x0e\x02\x03`H\xd0\x0a\
+      2\x01l\x00\x126\x04\x07\x01\xc0@l\x09\x1a\x01F@-\x80\x02\xd8\xc8\x05\xb2\x01F\x80H\xd8\x10<\
+      \x04\x84\x1c\xe79\xdfs\x8f&ffo{\xde\xf7\xbf$$\x9a\xaa\xc6\xfa\xae\xee\xcb\xf3`\x9a\x10\xe1\
+      \xc9\x03J\x00\x1aQ\xa9\x01\xe5[.\xb6\x17\xddS\xdd\xbe\xa2\xdbm\x96I$\xed\xb4\x94w?r\x0e~K\
+      \xab\xf7\x18\xfd\xfb\x80\xf7\xaaHl\x1e\xb9IS\x03O\x1a\xa6\x90\xda\x95(\x0bij\xe5\x05\xb4\x86\
+      \xf5%\x08\xb6*\xa9H\xb6\x90\xf55I\x0d\x87]n\xf9\xcc\x8e\xebk\xb6\xc9%\x92+i+~\xfc\x879\xc2>\
+      \x08\x0e\xa8\xd9~l\x03\xb3if\xc08\xa1\xc3\x92'\x04\x05Y\xbft\xcfY\xdcv\xb6\xdb\xde\xf7\xbd\
+      \xef}E\xb4\x95\xa8\x06F\xd2\xf3`\x1aP\xe1\xc9\x13\x82\x02\xac\xd9y\xb0\x0e\xcd\xa5\x9b\x0b\
+      \xe9=f\xbc\xd6\xdbm\x92I$\xb4T\x91\xc3\x87\xe4\x89\xe8@E\xe6\xcb\xad\x80nm,\xd8\x07\x148rD\
+      \xe0\x80\xe3\xbd\xf7\xae\xfa\xebU\xb6\xc9,\x92;i+b\xfa\xd8\x07\xdem*\xd8'\x048rD\xe0\x80\xab\
+      6^l\x03\xb3}\xec\xf3\x97\x1b\xad\xb6\xf6\xc9$\x96\x0bi+t\x13\xd0\x87\x0cH\x9f\x04\x05Y\xb2\
+      \xf3`\x1d\x9bK6\x09\xc1\x0e\x1c\x93\xefe\xed\xd7\x97m\xb6\xef{\xde\xf7\xb6\xf2\xdas\x0c\x0f\
+      \x97[.6\x01\xb5\xb4\xb3`\x9c\x10\xe1\xc9\x1c\xb8\x80\xab6^l\x05{\x1f\xae\xed\xd4\xdbm\x92\
+      \xc9$\xfa\xda\x12\xd1.\xb6\x09\x81\x0e\x1f$j\xe2\x03,\xd9y\xb0\x0e\xcd\xa5\x9b\x04\xe0\x87\
+      \xdfw\xcf\xd7+\xad\xb7\xebd\x92Y\x0d\xb4\x95\x91\xf9q\x01\xd5\x1bk\xcd\x81Vl\xbc\xd8'\x048r@\
+      \xe2\x83\x95.\xfd\x04\x84\x1e\x82B\x0f\xc0\x90\x83\xd0HA\xfc]\xc9\x14\xe1BB\x02\x7f\xd1l";
+
+#[cfg(test)]
+const ALL_BYTES: &[u8] = b"BZh91AY&SY\xb6\xb5\xee\x95\x00\x00\x00\x7f\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\
+      \xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\xb0\x00\
+      \xc5R\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x010\x09\x80\x00\x00\
+      \x00\x00\x00\x00\x00\x00\x00\x00L\x00\x04\xc0\x04\x98\x00&\x00\x02`\x00\x00\x00\x00\x00\x00\
+      \x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x02L\
+      \x00\x13\x00\x010\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\
+      \x00\x00\x00\x00\x00\x00\x00\x00\x00\x03\xf0\x08\x0c\x10\x14\x18\x1c $(,048<@DHLPTX\x5c`dhlp\
+      tx|\x80\x84\x88\x8c\x90\x94\x98\x9c\xa0\xa4\xa8\xac\xb0\xb4\xb8\xbc\xc0\xc4\xc8\xcc\xd0\xd4\
+      \xd8\xdc\xe0\xe4\xe8\xec\xf0\xf4\xf8\xfd\x01\x05\x09\x0d\x11\x15\x19\x1d!%)-159=AEIMQUY]aeim\
+      quy}\x81\x85\x89\x8d\x91\x85\x89\x8d\x91\x95\x99\x9d\xa1\xa5\xa9\xad\xb1\xb5\xb9\xbd\xc1\xc5\
+      \xc9\xcd\xd1\xd5\xd9\xdd\xe1\xe5\xe9\xed\xf1\xf5\xf9\xfe\x02\x06\x0a\x0e\x12\x16\x1a\x1e\x22\
+      &*.26:>BFJVZ^bfjnrvz~\x82\x86\x8a\x8e\x92\x96\x9a\x9e\xa2\xa6\xaa\xae\xb2\xb6\xba\xbe\xc2\
+      \xc6\xca\xce\xd2\xd6\xda\xde\xe2\xe6\xea\xee\xf2\xf6\xfa\xff\x03\x07\x0b\x0f\x13\x17\x1b\x17\
+      \x1b\x1f#'+/37;?CGKOSW[_cgkosw{\x7f\x83\x87\x8b\x8f\x93\x97\x9b\x9f\xa3\xa7\xab\xaf\xb3\xb7\
+      \xbb\xbf\xc3\xc7\xcb\xcf\xd3\xd7\xdb\xdf\xe3\xe7\xeb\xef\xf3\xf4]\xc9\x14\xe1BB\xda\xd7\xbaT";
+
+#[test]
+fn test_bzip2() {
+    // generated with python's bz2 module
+    assert_eq!(decompress(b"BZh9\x17rE8P\x90\x00\x00\x00\x00").unwrap(), b"");
+    assert_eq!(decompress(BANANA).unwrap(), b"banana");
+    let mut runs = vec![b'a'; 1000];
+    runs.extend_from_slice(b"bbb");
+    assert_eq!(decompress(RUNS).unwrap(), runs);
+    let lines: String = (0..300).map(|i| format!("line {}\n", i * 7)).collect();
+    assert_eq!(decompress(LINES).unwrap(), lines.as_bytes());
+    let all_bytes: Vec<u8> = (0..256).map(|b| b as u8).collect();
+    assert_eq!(decompress(ALL_BYTES).unwrap(), all_bytes);
+
+    // two streams back to back
+    let mut twice = BANANA.to_vec();
+    twice.extend_from_slice(BANANA);
+    assert_eq!(decompress(&twice).unwrap(), b"bananabanana");
+
+    let mut corrupt = LINES.to_vec();
+    let mid = corrupt.len() / 2;
+    corrupt[mid] ^= 0x10;
+    assert!(decompress(&corrupt).is_err());
+    assert!(decompress(&LINES[..LINES.len() - 4]).is_err());
+}
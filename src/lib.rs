@@ -4,45 +4,225 @@
 //! MediaWiki).
 //!
 //! For more into, see the [OpenZIM website](http://www.openzim.org/wiki/OpenZIM)
+//!
+//! XZ clusters are decoded in pure rust by default.  Building with the `liblzma` feature decodes
+//! them with liblzma instead, through the `xz2` crate (which links the system's liblzma, or
+//! builds its own copy).  That was about 1.3-1.4x faster at decompressing whole clusters of an
+//! XZ archive (the `blobs/get_cluster` benchmark in `benches/read.rs`), at the cost of linking
+//! against a C library.
+//!
+//! Hashing article data (`Zim::article_digest`, `Zim::digest_all`) and comparing archives
+//! (`Zim::diff`, `Zim::content_equivalent`) need the `sha2` feature, which hashes with the `sha2`
+//! crate.  Picking random articles (`Zim::random_article`) needs the `rand` feature.
+//!
+//! Building with the `remote` feature adds `Zim::open_url`, which reads an archive from a web
+//! server with HTTP range requests, fetching clusters as they're needed rather than downloading
+//! the whole file.
+//!
+//! On 32-bit platforms, only archives smaller than 4GB can be read: the whole file is addressed
+//! through one memory map (or buffer), so offsets past that give `OutOfRange` errors.
 //! 
 
 extern crate byteorder;
 extern crate memmap;
+extern crate miniz_oxide;
 extern crate xz_decom;
+extern crate xz_embedded_sys;
+extern crate md5;
+#[cfg(unix)]
+extern crate libc;
+#[cfg(feature = "remote")]
+extern crate ureq;
+#[cfg(feature = "liblzma")]
+extern crate xz2;
+#[cfg(feature = "sha2")]
+extern crate sha2;
+#[cfg(feature = "rand")]
+extern crate rand;
+
+mod bzip2;
+#[cfg(feature = "liblzma")]
+mod liblzma;
+#[cfg(feature = "remote")]
+mod remote;
+#[cfg(test)]
+mod test_zim;
 
 use byteorder::{LittleEndian, ReadBytesExt};
+#[cfg(test)]
+use byteorder::WriteBytesExt;
+use md5::Digest;
 use std::io::Cursor;
 use memmap::{Mmap, MmapView};
-use xz_decom::{decompress, XZError};
+use xz_decom::XZError;
+use xz_embedded_sys as raw;
 
 use std::fs::File;
 use std::io::Read;
 use std::io::BufRead;
-use std::path::Path;
+use std::io::Write;
+use std::io;
+use std::path::{Component, Path, PathBuf};
 use std::error::Error;
 use std::convert::From;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::ops::Range;
+use std::borrow::Cow;
+use std::time::{Duration, Instant};
+
 
+/// The general category of a `ParsingError`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    /// The file doesn't start with the ZIM magic number
+    BadMagic,
+    /// The data ended before something could be fully read
+    Truncated,
+    /// Compressed data couldn't be decompressed
+    Decompress,
+    /// A url, title, or mimetype wasn't valid UTF-8
+    BadUtf8,
+    /// An index or offset pointed outside of where it should
+    OutOfRange,
+    /// The archive uses a version of the ZIM format this crate doesn't support
+    UnsupportedVersion,
+    /// The stored checksum doesn't match the file's contents
+    ChecksumMismatch,
+    /// Following redirects led back to an entry that was already visited
+    RedirectCycle,
+    /// The entry is a redirect, a link target, or a deleted entry, so it doesn't have any data
+    NoContent,
+    /// The url or title table isn't in sorted order
+    Unsorted,
+    /// Some other I/O error
+    Io,
+}
 
 /// An error type for parsing errors
+#[derive(Debug)]
 pub struct ParsingError {
+    kind: ParseErrorKind,
     msg: &'static str,
-    cause: Option<Box<Error>>
+    cause: Option<Box<dyn Error>>,
+
+    // where the error happened, if known
+    offset: Option<u64>,
+    url_index: Option<u32>,
+    cluster_index: Option<u32>,
+}
+
+impl ParsingError {
+    fn new(kind: ParseErrorKind, msg: &'static str) -> ParsingError {
+        ParsingError {
+            kind: kind,
+            msg: msg,
+            cause: None,
+            offset: None,
+            url_index: None,
+            cluster_index: None,
+        }
+    }
+
+    /// Records the file offset this error happened at, unless a more specific one is already set
+    fn at_offset(mut self, offset: u64) -> ParsingError {
+        self.offset = self.offset.or(Some(offset));
+        self
+    }
+
+    /// Records the URL index of the entry this error happened in
+    fn in_entry(mut self, idx: u32) -> ParsingError {
+        self.url_index = self.url_index.or(Some(idx));
+        self
+    }
+
+    /// Records the index of the cluster this error happened in
+    fn in_cluster(mut self, idx: u32) -> ParsingError {
+        self.cluster_index = self.cluster_index.or(Some(idx));
+        self
+    }
+
+    /// What sort of error this is
+    pub fn kind(&self) -> ParseErrorKind {
+        self.kind
+    }
+
+    /// The offset from the start of the file where this error happened, if known
+    pub fn offset(&self) -> Option<u64> {
+        self.offset
+    }
+
+    /// The URL index of the directory entry this error happened in, if any
+    pub fn url_index(&self) -> Option<u32> {
+        self.url_index
+    }
+
+    /// The index of the cluster this error happened in, if any
+    pub fn cluster_index(&self) -> Option<u32> {
+        self.cluster_index
+    }
+}
+
+impl std::fmt::Display for ParsingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        try!(write!(f, "{}", self.msg));
+        if let Some(ref cause) = self.cause {
+            try!(write!(f, ": {}", cause));
+        }
+        if let Some(idx) = self.url_index {
+            try!(write!(f, " at url index {}", idx));
+        }
+        if let Some(idx) = self.cluster_index {
+            try!(write!(f, " in cluster {}", idx));
+        }
+        if let Some(offset) = self.offset {
+            try!(write!(f, ", file offset {:#x}", offset));
+        }
+        Ok(())
+    }
+}
+
+impl Error for ParsingError {
+    fn description(&self) -> &str {
+        self.msg
+    }
+
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.cause.as_deref()
+    }
+}
+
+impl From<ParsingError> for io::Error {
+    fn from(e: ParsingError) -> io::Error {
+        let kind = match e.kind {
+            ParseErrorKind::Truncated => io::ErrorKind::UnexpectedEof,
+            ParseErrorKind::NoContent => io::ErrorKind::InvalidInput,
+            _ => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, e.to_string())
+    }
 }
 
 impl From<XZError> for ParsingError {
     fn from(e: XZError) -> ParsingError {
         ParsingError {
-            msg: "Error decoding compressed data",
-            cause: Some(Box::new(e))
+            cause: Some(Box::new(e)),
+            ..ParsingError::new(ParseErrorKind::Decompress, "Error decoding compressed data")
         }
     }
 }
 
 impl From<byteorder::Error> for ParsingError {
     fn from(e: byteorder::Error) -> ParsingError {
+        let kind = match e {
+            byteorder::Error::UnexpectedEOF => ParseErrorKind::Truncated,
+            byteorder::Error::Io(_) => ParseErrorKind::Io,
+        };
         ParsingError {
-            msg: "Error reading bytestream",
-            cause: Some(Box::new(e))
+            cause: Some(Box::new(e)),
+            ..ParsingError::new(kind, "Error reading bytestream")
         }
     }
 }
@@ -50,32 +230,108 @@ impl From<byteorder::Error> for ParsingError {
 impl From<std::string::FromUtf8Error> for ParsingError {
     fn from(e: std::string::FromUtf8Error) -> ParsingError {
         ParsingError {
-            msg: "Error converting to string",
-            cause: Some(Box::new(e))
+            cause: Some(Box::new(e)),
+            ..ParsingError::new(ParseErrorKind::BadUtf8, "Error converting to string")
+        }
+    }
+}
+
+impl From<std::str::Utf8Error> for ParsingError {
+    fn from(e: std::str::Utf8Error) -> ParsingError {
+        ParsingError {
+            cause: Some(Box::new(e)),
+            ..ParsingError::new(ParseErrorKind::BadUtf8, "Error converting to string")
         }
     }
 }
 
 impl From<std::io::Error> for ParsingError {
     fn from(e: std::io::Error) -> ParsingError {
+        // XzReader reports decoding errors as io errors wrapping an XZRawError
+        let is_xz_error = e.get_ref().is_some_and(|inner| inner.is::<raw::XZRawError>());
+        let kind = if is_xz_error {
+            ParseErrorKind::Decompress
+        } else if e.kind() == io::ErrorKind::UnexpectedEof {
+            ParseErrorKind::Truncated
+        } else {
+            ParseErrorKind::Io
+        };
         ParsingError {
-            msg: "Error reading bytestream",
-            cause: Some(Box::new(e))
+            cause: Some(Box::new(e)),
+            ..ParsingError::new(kind, "Error reading bytestream")
         }
     }
 }
 
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum MimeType {
     /// A special "MimeType" that represents a redirection
     Redirect,
     LinkTarget,
     DeletedEntry,
-    Type(String)
+    Type(String),
+    /// A mimetype id that isn't in the archive's mime list
+    ///
+    /// Entries with an unknown mimetype are otherwise read as normal articles.
+    Unknown(u16)
+}
+
+/// Mimetypes outside of `text/` whose content is text
+const TEXT_MIMETYPES: [&str; 5] = [
+    "application/json",
+    "application/javascript",
+    "application/x-javascript",
+    "application/ecmascript",
+    "application/xml",
+];
+
+impl MimeType {
+    /// Returns true if content of this type is text, which can be read with `String::from_utf8`
+    ///
+    /// These are recognized as text:
+    ///
+    /// | Mimetype | Examples |
+    /// |----------|----------|
+    /// | `text/*` | `text/html`, `text/css`, `text/plain` |
+    /// | JSON | `application/json`, and any `+json` type |
+    /// | JavaScript | `application/javascript`, `application/x-javascript`, `application/ecmascript` |
+    /// | XML | `application/xml`, and any `+xml` type such as `image/svg+xml` |
+    ///
+    /// Parameters such as `; charset=utf-8` are ignored, and case doesn't matter.  Everything
+    /// else, including redirects, link targets, deleted entries, and unknown mimetypes, is
+    /// treated as binary.  Text is usually UTF-8, but check the charset parameter if it matters.
+    pub fn is_text(&self) -> bool {
+        let mime = match *self {
+            MimeType::Type(ref mime) => mime,
+            _ => return false
+        };
+        let essence = mime.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+        essence.starts_with("text/")
+            || essence.ends_with("+xml")
+            || essence.ends_with("+json")
+            || TEXT_MIMETYPES.contains(&&essence[..])
+    }
+}
+
+/// Makes a `MimeType::Type`, so that entries can be compared against a literal with
+/// `entry.mime_type == MimeType::from("text/html")`
+///
+/// Every string becomes a `Type`, even ones that look like the names of the special variants;
+/// those can only be compared against as `MimeType::Redirect` and so on.
+impl<'a> From<&'a str> for MimeType {
+    fn from(s: &'a str) -> MimeType {
+        MimeType::Type(s.to_owned())
+    }
+}
+
+impl From<String> for MimeType {
+    fn from(s: String) -> MimeType {
+        MimeType::Type(s)
+    }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Target {
     /// Redirect specified as a URL index
     Redirect(u32),
@@ -83,403 +339,5177 @@ pub enum Target {
     Cluster(u32, u32)
 }
 
+/// How the data in a cluster is compressed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Compression {
+    /// Not compressed (compression type 0 or 1)
+    None,
+    /// zlib (compression type 2)
+    Zlib,
+    /// bzip2 (compression type 3)
+    Bzip2,
+    /// XZ/LZMA2 (compression type 4)
+    Xz,
+    /// zstd (compression type 5), which this crate can't decompress
+    Zstd,
+    /// Any other compression type, which this crate can't decompress
+    Unknown(u8),
+}
+
+impl Compression {
+    /// Interprets the compression type byte at the start of a cluster
+    ///
+    /// Only the low four bits give the compression type; the high bits are flags (see
+    /// `EXTENDED_CLUSTER`).
+    fn from_byte(b: u8) -> Compression {
+        match b & 0x0f {
+            0 | 1 => Compression::None,
+            2 => Compression::Zlib,
+            3 => Compression::Bzip2,
+            4 => Compression::Xz,
+            5 => Compression::Zstd,
+            b => Compression::Unknown(b),
+        }
+    }
+}
+
+/// The error for a cluster compressed in a way this crate can't decompress (zstd, or an unknown
+/// compression type)
+fn unsupported_compression(compression: Compression) -> ParsingError {
+    ParsingError {
+        cause: Some(From::from(format!("{:?} clusters can't be decompressed", compression))),
+        ..ParsingError::new(ParseErrorKind::Decompress, "Unsupported cluster compression")
+    }
+}
+
+/// The data of an article, along with its mimetype
+#[derive(Debug)]
+pub struct Blob {
+    pub mime: MimeType,
+    pub data: Vec<u8>,
+}
+
+/// The flag in a cluster's compression type byte marking an extended cluster, whose blob offset
+/// table has 8-byte offsets instead of 4-byte ones
+const EXTENDED_CLUSTER: u8 = 0x10;
+
+/// Returns the size of each offset in the blob offset table of a cluster with the given
+/// compression type byte
+fn blob_offset_size(comp_type: u8) -> u32 {
+    if comp_type & EXTENDED_CLUSTER != 0 { 8 } else { 4 }
+}
+
+/// XZ clusters bigger than this (compressed) are decoded with `read_cluster_from`, which uses less
+/// memory; smaller ones are decoded in one go, which is a little quicker
+#[cfg(not(feature = "liblzma"))]
+const STREAMING_XZ_THRESHOLD: usize = 1 << 20;
+
 /// A cluster of blobs
 ///
 /// Within an ZIM archive, clusters contain several blobs of data that are all compressed together.
 /// Each blob is the data for an article.
 //#[derive(Debug)]
+#[derive(Clone)]
 pub struct Cluster {
+    idx: u32,
     start_off: u64,
     end_off: u64,
     comp_type: u8,
     blob_list: Vec<u32>, // offsets into data
-    data: Vec<u8>,
+    data: Rc<Vec<u8>>,
     
 }
 
 impl Cluster {
     fn new(zim: &Zim, idx: u32) -> Result<Cluster, ParsingError> {
-        let idx = idx as usize;
-        let this_cluster_off = zim.cluster_list[idx];
-        let next_cluster_off = if idx < zim.cluster_list.len()-1 {
-            zim.cluster_list[idx + 1]
-        } else {
-            zim.checksum_off
-        };
+        Cluster::parse(zim, idx).map_err(|e| e.in_cluster(idx).at_offset(zim.cluster_offset(idx)))
+    }
 
-        assert!(next_cluster_off > this_cluster_off);
-        let total_cluster_size: usize = (next_cluster_off - this_cluster_off) as usize;
+    fn parse(zim: &Zim, idx: u32) -> Result<Cluster, ParsingError> {
+        let (this_cluster_off, next_cluster_off) = zim.cluster_range(idx);
 
-        let cluster_view = {
-            let mut view = unsafe{ zim.master_view.clone() };
-            let len = view.len();
-            view.restrict(this_cluster_off as usize, total_cluster_size);
-            view
-        };
-        let slice = unsafe{ cluster_view.as_slice() };
+        let slice = try!(zim.cluster_data(idx));
         let comp_type = slice[0];
-        let mut blob_list = Vec::new(); 
-        let data: Vec<u8> = if comp_type == 4 {
-            let data = try!(decompress(&slice[1..total_cluster_size]));
-            println!("Decompressed {} bytes of data", data.len());
-            data
-        } else {
-            Vec::from(&slice[1..total_cluster_size])
+        let start = zim.options.on_decompress.as_ref().map(|_| Instant::now());
+        let offset_size = blob_offset_size(comp_type);
+        let data: Vec<u8> = match Compression::from_byte(comp_type) {
+            Compression::Xz => try!(decompress_xz(&slice[1..], offset_size)),
+            Compression::Bzip2 => {
+                try!(bzip2::decompress(&slice[1..]).map_err(|msg| ParsingError::new(ParseErrorKind::Decompress, msg)))
+            }
+            Compression::Zlib => {
+                let mut data = Vec::new();
+                try!(decompress_zlib_into(&slice[1..], &mut data));
+                data
+            }
+            Compression::None => Vec::from(&slice[1..]),
+            compression => return Err(unsupported_compression(compression))
         };
-        let datalen = data.len();
-        {
-            let mut cur = Cursor::new(&data);
-            loop {
-                let offset = try!(cur.read_u32::<LittleEndian>());
-                blob_list.push(offset);
-                if offset as usize >= datalen {
-                    //println!("at end");
-                    break;
-                }
+        if let (Some(hook), Some(start)) = (zim.options.on_decompress.as_ref(), start) {
+            if Compression::from_byte(comp_type) != Compression::None {
+                (hook.0)(&DecompressInfo {
+                    cluster_idx: idx,
+                    compressed_len: next_cluster_off - this_cluster_off,
+                    decompressed_len: data.len(),
+                    duration: start.elapsed(),
+                });
             }
         }
+        let blob_list = try!(parse_blob_list(&data, offset_size));
 
         Ok(Cluster {
+            idx: idx,
             comp_type: comp_type,
             start_off: this_cluster_off,
             end_off: next_cluster_off,
-            data: data,
+            data: Rc::new(data),
             blob_list: blob_list,
         })
         
     }
+    /// Returns the data of the given blob
+    ///
+    /// Panics if idx is out of range; see `get_blob_checked`.
     pub fn get_blob(&self, idx: u32) -> &[u8] {
-        let this_blob_off = self.blob_list[idx as usize] as usize;
-	if self.blob_list.len() > idx as usize + 1 {
-        let next_blob_off = self.blob_list[idx as usize + 1] as usize;
-            &self.data[this_blob_off..next_blob_off]
-        } else {
-            &self.data[this_blob_off..]
+        match self.get_blob_checked(idx) {
+            Ok(blob) => blob,
+            Err(e) => panic!("{}", e)
         }
     }
-}
-
-/// Holds metadata about an article
-#[derive(Debug)]
-pub struct DirectoryEntry {
-    pub mime_type: MimeType,
-    pub namespace: char,
-    pub revision: u32,
-    pub url: String,
-    pub title: String,
-    pub target: Option<Target>
-}
 
-impl DirectoryEntry {
-    fn new(zim: &Zim, s: &[u8]) -> Result<DirectoryEntry, ParsingError> {
-        let mut cur = Cursor::new(s);
-        let mime_id = try!(cur.read_u16::<LittleEndian>());
-        let mime_type = try!(zim.get_mimetype(mime_id).ok_or(ParsingError{msg: "No such Mimetype", cause: None}));
-        let _ = try!(cur.read_u8());
-        let namespace = try!(cur.read_u8());
-        let rev = try!(cur.read_u32::<LittleEndian>());
-        let mut target = None;
+    /// Returns the data of the given blob, or an error if idx is out of range
+    pub fn get_blob_checked(&self, idx: u32) -> Result<&[u8], ParsingError> {
+        blob_at(&self.data, &self.blob_list, idx).map_err(|e| e.in_cluster(self.idx).at_offset(self.start_off))
+    }
 
+    /// Returns the number of blobs in this cluster
+    ///
+    /// The offset table ends with an extra offset marking the end of the last blob, which isn't
+    /// counted, so the valid blob indices are `0..blob_count()`.
+    pub fn blob_count(&self) -> u32 {
+        self.blob_list.len().saturating_sub(1) as u32
+    }
 
-        if mime_type == MimeType::Redirect {
-            // this is an index into the URL table
-            target = Some(Target::Redirect(try!(cur.read_u32::<LittleEndian>())));
-        } else if mime_type == MimeType::LinkTarget || mime_type == MimeType::DeletedEntry {
+    /// Returns the cluster's blob offset table
+    ///
+    /// These are byte offsets into the decompressed cluster.  Blob `i` runs from `offsets[i]` to
+    /// `offsets[i + 1]`, so there's one more offset than there are blobs; the first offset is the
+    /// size of the table itself.
+    pub fn blob_offsets(&self) -> &[u32] {
+        &self.blob_list
+    }
 
+    /// Returns the size of the given blob, or None if idx is out of range
+    pub fn blob_size(&self, idx: u32) -> Option<usize> {
+        let idx = idx as usize;
+        if idx + 1 < self.blob_list.len() {
+            Some((self.blob_list[idx + 1] - self.blob_list[idx]) as usize)
         } else {
-            let cluster_number = try!(cur.read_u32::<LittleEndian>());
-            let blob_number = try!(cur.read_u32::<LittleEndian>());
-            target = Some(Target::Cluster(cluster_number, blob_number));
+            None
         }
-       
-        let url = {
-            let mut vec = Vec::new();
-            let size = try!(cur.read_until(0, &mut vec));
-            vec.truncate(size - 1);
-            try!(String::from_utf8(vec))
-        };
-        let title = {
-            let mut vec = Vec::new();
-            let size = try!(cur.read_until(0, &mut vec));
-            vec.truncate(size - 1);
-            try!(String::from_utf8(vec))
-        };
+    }
 
+    /// The size of this cluster once decompressed, including the blob offset table at its start
+    pub fn decompressed_len(&self) -> usize {
+        self.data.len()
+    }
 
-        Ok(DirectoryEntry{
-            mime_type: mime_type,
-            namespace: std::char::from_u32(namespace as u32).unwrap(),
-            revision: rev,
-            url: url,
-            title: title,
-            target: target,
-        })
+    /// The size of this cluster as stored in the archive, including the compression type byte
+    pub fn compressed_len(&self) -> u64 {
+        self.end_off - self.start_off
     }
-}
 
-/// Represents a ZIM file
-#[allow(dead_code)]
-pub struct Zim {
-    // Zim structure data:
+    /// Returns how many times bigger this cluster is decompressed than as stored
+    ///
+    /// This is `decompressed_len / compressed_len`, except that it's always 1.0 for uncompressed
+    /// clusters.
+    pub fn compression_ratio(&self) -> f64 {
+        if !self.is_compressed() || self.compressed_len() == 0 {
+            return 1.0;
+        }
+        self.decompressed_len() as f64 / self.compressed_len() as f64
+    }
 
-    version: u32,
-    // uuid_1
-    // uuid_2
-    /// Number of articles in this archive
-    pub article_count: u32,
-    /// Number of clusters in this archive
-    pub cluster_count: u32,
-    url_tbl_off: u64, //offset from the start of the file
-    title_tbl_off: u64, //offset from the start of the file
-    cluster_tbl_off: u64,
-    mime_tbl_off: u64, // should always be 80
-    /// If Main Page is defined, this is the index to the page
-    pub main_page_idx: Option<u32>, // an index into the url table
-    layout_page_idx: Option<u32>,
-    checksum_off: u64,
+    /// The file offsets where this cluster starts and ends
+    ///
+    /// The range covers the compression type byte and the (possibly compressed) data, the same
+    /// bytes `Zim::raw_cluster_bytes` returns.
+    pub fn byte_range(&self) -> Range<u64> {
+        self.start_off..self.end_off
+    }
 
-    // internal variables:
-    f: File,
-    master_view: MmapView,
+    /// How this cluster is compressed in the archive
+    pub fn compression(&self) -> Compression {
+        Compression::from_byte(self.comp_type)
+    }
 
-    /// List of mimetypes used in this ZIM archive
-    mime_table: Vec<String>, // a list of mimetypes
-    url_list: Vec<u64>, // a list of offsets
-    article_list: Vec<u32>, // a list of indicies into url_list
-    cluster_list: Vec<u64>, // a list of offsets
+    /// Returns true if this cluster is stored compressed (with any compression other than
+    /// `Compression::None`)
+    pub fn is_compressed(&self) -> bool {
+        self.compression() != Compression::None
+    }
 
+    /// Returns true if this is an extended cluster, whose blob offset table uses 8-byte offsets
+    ///
+    /// Writers use these for clusters too big for 4-byte offsets.  Either way, the offsets are
+    /// returned as `blob_offsets`.
+    pub fn is_extended(&self) -> bool {
+        self.comp_type & EXTENDED_CLUSTER != 0
+    }
+}
 
+/// Decompresses an XZ cluster with the pure-rust decoder
+#[cfg(not(feature = "liblzma"))]
+fn decompress_xz(data: &[u8], offset_size: u32) -> Result<Vec<u8>, ParsingError> {
+    if data.len() >= STREAMING_XZ_THRESHOLD {
+        read_cluster_from(try!(XzReader::new(data)), offset_size)
+    } else {
+        Ok(try!(xz_decom::decompress(data)))
+    }
+}
 
+/// Decompresses an XZ cluster with liblzma
+#[cfg(feature = "liblzma")]
+fn decompress_xz(data: &[u8], _offset_size: u32) -> Result<Vec<u8>, ParsingError> {
+    liblzma::decompress(data).map_err(|msg| ParsingError::new(ParseErrorKind::Decompress, msg))
 }
 
-pub struct DirectoryIterator<'a> {
-    max_articles: u32,
-    article_to_yield: u32,
-    zim: &'a Zim
+/// Decompresses an XZ cluster into `out`, which is cleared first
+///
+/// Unlike `decompress_xz`, this always streams, so that `out`'s allocation is reused.
+#[cfg(not(feature = "liblzma"))]
+fn decompress_xz_into(data: &[u8], offset_size: u32, out: &mut Vec<u8>) -> Result<(), ParsingError> {
+    read_cluster_into(try!(XzReader::new(data)), offset_size, out)
 }
 
-impl<'a> DirectoryIterator<'a> {
-    fn new(zim: &'a Zim) -> DirectoryIterator<'a> {
-        DirectoryIterator {
-            max_articles: zim.article_count,
-            article_to_yield: 0,
-            zim: zim
-        }
-    }
+/// Decompresses an XZ cluster into `out` with liblzma
+#[cfg(feature = "liblzma")]
+fn decompress_xz_into(data: &[u8], _offset_size: u32, out: &mut Vec<u8>) -> Result<(), ParsingError> {
+    liblzma::decompress_into(data, out).map_err(|msg| ParsingError::new(ParseErrorKind::Decompress, msg))
 }
 
-impl<'a> std::iter::Iterator for DirectoryIterator<'a> {
-    type Item = DirectoryEntry;
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.article_to_yield >= self.max_articles {
-            None 
-        } else {
-            let dir_entry_ptr = self.zim.url_list[self.article_to_yield as usize] as usize;
-            self.article_to_yield += 1;
-            let dir_view = {
-                let mut view = unsafe{ self.zim.master_view.clone() };
-                let len = view.len();
-                view.restrict(dir_entry_ptr, len - dir_entry_ptr);
-                view
-            };
-            let slice = unsafe{ dir_view.as_slice() };
+/// Decompresses a zlib cluster into `out`, which is cleared first
+///
+/// This drives miniz_oxide's decoder directly, rather than through `decompress_to_vec_zlib`, so
+/// that `out`'s allocation is reused.  The Adler-32 checksum at the end of the stream is checked.
+fn decompress_zlib_into(data: &[u8], out: &mut Vec<u8>) -> Result<(), ParsingError> {
+    use miniz_oxide::inflate::core::{decompress, inflate_flags, DecompressorOxide};
+    use miniz_oxide::inflate::TINFLStatus;
 
-            if let Ok(entry) = DirectoryEntry::new(self.zim, slice) {
-                Some(entry)
-            } else {
-                None
+    let flags = inflate_flags::TINFL_FLAG_PARSE_ZLIB_HEADER | inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF;
+    let mut decoder = Box::<DecompressorOxide>::default();
+    // the decoder needs room for all of the output up front; reuse whatever `out` already has,
+    // and otherwise guess, since clusters usually compress to around a quarter of their size
+    let initial = if out.capacity() > 0 { out.capacity() } else { std::cmp::max(data.len().saturating_mul(4), 64) };
+    out.clear();
+    out.resize(initial, 0);
+    let (mut in_pos, mut out_pos) = (0, 0);
+    loop {
+        let (status, read, written) = decompress(&mut decoder, &data[in_pos..], out, out_pos, flags);
+        in_pos += read;
+        out_pos += written;
+        match status {
+            TINFLStatus::Done => {
+                out.truncate(out_pos);
+                return Ok(());
+            }
+            TINFLStatus::HasMoreOutput => {
+                let len = out.len();
+                out.resize(len.saturating_mul(2), 0);
+            }
+            status => {
+                out.truncate(out_pos);
+                return Err(ParsingError {
+                    cause: Some(From::from(format!("zlib decoder stopped with {:?}", status))),
+                    ..ParsingError::new(ParseErrorKind::Decompress, "Corrupt zlib data")
+                });
             }
         }
     }
 }
 
-impl Zim {
-    /// Loads a Zim file
-    ///
-    /// Loads a Zim file and parses the header, and the url, title, and cluster offset tables.  The
-    /// rest of the data isn't parsed until it's needed, so this should be fairly quick.
-    pub fn new<P: AsRef<Path>>(p: P) -> Result<Zim, ParsingError> {
-        let mut f = try!(File::open(p));
-        let mmap = try!(Mmap::open(&f, memmap::Protection::Read));
-        let master_view = mmap.into_view();
+/// Reads the blob offset table at the start of a (decompressed) cluster
+///
+/// `offset_size` is the size of each offset, from `blob_offset_size`.
+fn parse_blob_list(data: &[u8], offset_size: u32) -> Result<Vec<u32>, ParsingError> {
+    let mut blob_list = Vec::new();
+    let mut cur = Cursor::new(data);
+    // the first offset points just past the offset table, so it tells us how many
+    // offsets there are
+    let first_off = try!(read_blob_offset(&mut cur, offset_size));
+    if first_off == 0 || first_off % offset_size != 0 || first_off as usize > data.len() {
+        return Err(ParsingError::new(ParseErrorKind::OutOfRange, "Invalid blob offset table"));
+    }
+    blob_list.push(first_off);
+    for _ in 1..first_off / offset_size {
+        let offset = try!(read_blob_offset(&mut cur, offset_size));
+        if offset < *blob_list.last().unwrap() || offset as usize > data.len() {
+            return Err(ParsingError::new(ParseErrorKind::OutOfRange, "Invalid blob offsets"));
+        }
+        blob_list.push(offset);
+    }
+    Ok(blob_list)
+}
 
-        let header_view = {
-            let mut view = unsafe{ master_view.clone() };
-            view
-        };
+/// Reads one offset of a blob offset table
+///
+/// Offsets in extended clusters are 8 bytes, but clusters are decompressed into memory whole, so
+/// offsets that don't fit in 4 bytes are rejected.
+fn read_blob_offset<R: Read>(r: &mut R, offset_size: u32) -> Result<u32, ParsingError> {
+    if offset_size == 8 {
+        let offset = try!(r.read_u64::<LittleEndian>());
+        if offset > u32::MAX as u64 {
+            return Err(ParsingError::new(ParseErrorKind::OutOfRange, "Blob offset is too big to read"));
+        }
+        Ok(offset as u32)
+    } else {
+        Ok(try!(r.read_u32::<LittleEndian>()))
+    }
+}
 
-        let mut header_cur = Cursor::new( unsafe{ header_view.as_slice() } );
+/// Returns the given blob out of a (decompressed) cluster's data, using its blob offset table
+fn blob_at<'a>(data: &'a [u8], blob_list: &[u32], idx: u32) -> Result<&'a [u8], ParsingError> {
+    // the last offset marks the end of the last blob, rather than the start of another one; the
+    // data can carry on past it (such as padding after a compressed stream), which isn't part of
+    // any blob
+    let blob_count = blob_list.len().saturating_sub(1);
+    if idx as usize >= blob_count {
+        return Err(ParsingError {
+            cause: Some(From::from(format!("blob index {} out of range, cluster has {} blobs", idx, blob_count))),
+            ..ParsingError::new(ParseErrorKind::OutOfRange, "No such blob")
+        });
+    }
+    let this_blob_off = blob_list[idx as usize] as usize;
+    let next_blob_off = blob_list[idx as usize + 1] as usize;
+    if this_blob_off > next_blob_off || next_blob_off > data.len() {
+        return Err(ParsingError::new(ParseErrorKind::OutOfRange, "Invalid blob offsets"));
+    }
+    Ok(&data[this_blob_off..next_blob_off])
+}
 
-        let magic = try!(header_cur.read_u32::<LittleEndian>());
-        assert_eq!(magic, 72173914);
-        let version = try!(header_cur.read_u32::<LittleEndian>());
-        let uuid_1 = try!(header_cur.read_u64::<LittleEndian>());
-        let uuid_2 = try!(header_cur.read_u64::<LittleEndian>());
-        let article_count = try!(header_cur.read_u32::<LittleEndian>());
-        let cluster_count = try!(header_cur.read_u32::<LittleEndian>());
-        let url_ptr_pos = try!(header_cur.read_u64::<LittleEndian>());
-        let title_ptr_pos = try!(header_cur.read_u64::<LittleEndian>());
-        let cluster_ptr_pos = try!(header_cur.read_u64::<LittleEndian>());
-        let mime_list_pos = try!(header_cur.read_u64::<LittleEndian>());
-        assert_eq!(mime_list_pos, 80);
-        let main_page = try!(header_cur.read_u32::<LittleEndian>());
-        let layout_page = try!(header_cur.read_u32::<LittleEndian>());
-        let checksum_pos = try!(header_cur.read_u64::<LittleEndian>());
-        assert_eq!(header_cur.position(), 80);
+/// A cluster that borrows its data straight from the archive when it can
+///
+/// Returned by `Zim::cluster_view`.  Uncompressed clusters are read in place, without copying
+/// anything; compressed clusters are decompressed (going through the cluster cache, if it's
+/// enabled) just like `Zim::get_cluster`.
+pub struct ClusterRef<'a> {
+    idx: u32,
+    start_off: u64,
+    blob_list: Vec<u32>,
+    data: ClusterBytes<'a>,
+}
 
-        println!("version: {}", version);
-        println!("article_count: {}", article_count);
-        println!("cluster_count: {}", cluster_count);
-        println!("mime_list_pos: {}", mime_list_pos);
+enum ClusterBytes<'a> {
+    Borrowed(&'a [u8]),
+    Owned(Rc<Vec<u8>>),
+}
 
+impl<'a> ClusterRef<'a> {
+    /// Returns the data of the given blob
+    ///
+    /// Panics if idx is out of range; see `get_blob_checked`.
+    pub fn get_blob(&self, idx: u32) -> &[u8] {
+        match self.get_blob_checked(idx) {
+            Ok(blob) => blob,
+            Err(e) => panic!("{}", e)
+        }
+    }
 
-        // the mime table is always directly after the 80-byte header, so we'll keep
-        // using our header cursor 
-        let mime_table = {
-            let mut mime_table = Vec::new();
-            loop {
-                let mut mime_buf = Vec::new();
-                if let Ok(size) = header_cur.read_until(0, &mut mime_buf) {
-                    if size <= 1 { break; }
-                    mime_buf.truncate(size - 1);
-                    mime_table.push(try!(String::from_utf8(mime_buf)));
-                }
-            }
-            mime_table
+    /// Returns the data of the given blob, or an error if idx is out of range
+    pub fn get_blob_checked(&self, idx: u32) -> Result<&[u8], ParsingError> {
+        let data: &[u8] = match self.data {
+            ClusterBytes::Borrowed(data) => data,
+            ClusterBytes::Owned(ref data) => data,
         };
+        blob_at(data, &self.blob_list, idx).map_err(|e| e.in_cluster(self.idx).at_offset(self.start_off))
+    }
 
-        let url_list = {
-            let mut list = Vec::new();
-            let url_list_view = { let mut v = unsafe{master_view.clone()};
-                v.restrict(url_ptr_pos as usize, article_count as usize * 8);
-                v };
-            let mut url_cur = Cursor::new( unsafe{ url_list_view.as_slice() });
+    /// Returns the number of blobs in this cluster; see `Cluster::blob_count`
+    pub fn blob_count(&self) -> u32 {
+        self.blob_list.len().saturating_sub(1) as u32
+    }
 
-            for url_num in 0..article_count {
-                let pointer = try!(url_cur.read_u64::<LittleEndian>());
-                list.push(pointer);
-            }
-            list
-        };
-        
-        let article_list = {
-            let mut list = Vec::new();
-            let art_list_view = { let mut v = unsafe{master_view.clone()};
-                v.restrict(title_ptr_pos as usize, article_count as usize * 8);
-                v };
-            let mut art_cur = Cursor::new( unsafe{ art_list_view.as_slice() });
+    /// Returns true if this cluster's data is borrowed from the archive rather than decompressed
+    /// into memory
+    pub fn is_borrowed(&self) -> bool {
+        match self.data {
+            ClusterBytes::Borrowed(_) => true,
+            ClusterBytes::Owned(_) => false,
+        }
+    }
+}
 
-            for _ in 0..article_count {
-                let url_number = try!(art_cur.read_u32::<LittleEndian>());
-                list.push(url_number);
-            }
-            list
+/// Holds the bytes of an open archive
+enum Storage {
+    /// The file is memory-mapped
+    Mapped(MmapView),
+    /// The whole file has been read into memory
+    Buffered(Vec<u8>),
+    /// The file is on a web server, and is fetched with range requests as it's read
+    #[cfg(feature = "remote")]
+    Remote(remote::RemoteStorage),
+}
+
+impl Storage {
+    fn len(&self) -> usize {
+        match *self {
+            Storage::Mapped(ref view) => view.len(),
+            Storage::Buffered(ref data) => data.len(),
+            #[cfg(feature = "remote")]
+            Storage::Remote(ref remote) => remote.len,
+        }
+    }
+
+    /// Returns up to `len` bytes of the file starting at `off`
+    ///
+    /// All reads from the archive go through here.  The range is clamped to the end of the file,
+    /// so asking for data past the end gives a short (or empty) slice, which the parsing code then
+    /// reports as a `Truncated` error, rather than reading out of bounds.  Callers that don't know
+    /// how much they need, like the directory entry parser, pass `usize::MAX`.
+    ///
+    /// Local files are already in memory (or mapped), so this only fails for remote archives,
+    /// when fetching the data fails.
+    fn region(&self, off: usize, len: usize) -> Result<&[u8], ParsingError> {
+        let slice = match *self {
+            // This is only unsafe in that the file could be changed by someone else while it's
+            // mapped.  There's nothing we can do about that, and ZIM files aren't expected to be
+            // modified.
+            Storage::Mapped(ref view) => unsafe { view.as_slice() },
+            Storage::Buffered(ref data) => data,
+            #[cfg(feature = "remote")]
+            Storage::Remote(ref remote) => return remote.region(off, len),
         };
+        let start = std::cmp::min(off, slice.len());
+        let end = std::cmp::min(off.saturating_add(len), slice.len());
+        Ok(&slice[start..end])
+    }
 
+    /// Copies bytes of the file starting at `off` into `buf`, returning how many were copied
+    ///
+    /// Fewer than `buf.len()` bytes are copied only at the end of the file.  Unlike `region`,
+    /// this doesn't need the bytes to already be in memory, so it's what code that streams
+    /// through the file a piece at a time should use.
+    fn read_at(&self, off: u64, buf: &mut [u8]) -> Result<usize, ParsingError> {
+        let off = if off > usize::MAX as u64 { usize::MAX } else { off as usize };
+        let data = try!(self.region(off, buf.len()));
+        buf[..data.len()].copy_from_slice(data);
+        Ok(data.len())
+    }
+}
 
-        let cluster_list = {
-            let mut list = Vec::new();
-            let cluster_list_view = { let mut v = unsafe{master_view.clone()};
-                v.restrict(cluster_ptr_pos as usize, cluster_count as usize * 8);
-                v };
-            let mut cluster_cur = Cursor::new( unsafe{ cluster_list_view.as_slice() });
+/// How much of the file is hashed at a time when computing the checksum
+const CHECKSUM_CHUNK_SIZE: usize = 1 << 20;
 
-            for cluster_num in 0..cluster_count {
-                let pointer = try!(cluster_cur.read_u64::<LittleEndian>());
-                list.push(pointer);
+/// Converts a file offset (or length) to a usize, for indexing into the file
+///
+/// On 32-bit platforms, offsets of 4GB and up don't fit; rather than truncating them, which would
+/// read from the wrong place, this gives an error.
+fn to_usize(off: u64) -> Result<usize, ParsingError> {
+    if off > usize::MAX as u64 {
+        return Err(ParsingError::new(ParseErrorKind::OutOfRange, "Offset exceeds the addressable range on this platform")
+                   .at_offset(off));
+    }
+    Ok(off as usize)
+}
+
+/// Parses the size out of an illustration's metadata key, such as `Illustration_48x48@1`
+///
+/// Returns None if the key isn't an illustration, or if it isn't square or at a scale of 1.
+fn parse_illustration_size(key: &str) -> Option<u32> {
+    if !key.starts_with("Illustration_") || !key.ends_with("@1") {
+        return None;
+    }
+    let dims = &key["Illustration_".len()..key.len() - 2];
+    let mut parts = dims.splitn(2, 'x');
+    match (parts.next().and_then(|w| w.parse().ok()), parts.next().and_then(|h| h.parse::<u32>().ok())) {
+        (Some(w), Some(h)) if w == h => Some(w),
+        _ => None
+    }
+}
+
+/// Reverses percent-encoding in a url
+///
+/// Links inside articles are percent-encoded (e.g. `Caf%C3%A9`), but the urls stored in the
+/// archive (and expected by `get_by_url` and friends) are not, so links need to go through this
+/// before being looked up.  Malformed escapes (such as `%zz` or a trailing `%`) are left as-is, and
+/// any bytes that don't decode to valid UTF-8 are replaced with U+FFFD.
+pub fn decode_url(encoded: &str) -> String {
+    fn hex_val(b: u8) -> Option<u8> {
+        match b {
+            b'0'..=b'9' => Some(b - b'0'),
+            b'a'..=b'f' => Some(b - b'a' + 10),
+            b'A'..=b'F' => Some(b - b'A' + 10),
+            _ => None
+        }
+    }
+
+    let bytes = encoded.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let (Some(hi), Some(lo)) = (hex_val(bytes[i + 1]), hex_val(bytes[i + 2])) {
+                decoded.push(hi << 4 | lo);
+                i += 3;
+                continue;
             }
-            list
-        };
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
 
+/// Returns true if a link starts with a URI scheme (like `https:` or `javascript:`)
+///
+/// That's a letter followed by letters, digits, `+`, `-` or `.`, then a `:`, all before the
+/// first `/`.  So a relative link to an entry with a colon in its url has to start with `./`
+/// (as in `./Category:Foo`), just as it would on the web.
+fn has_scheme(link: &str) -> bool {
+    let scheme = match link.find(':') {
+        Some(end) => &link[..end],
+        None => return false
+    };
+    let mut chars = scheme.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.')
+}
 
-        
-        Ok(Zim {
-           version: version,
-           article_count: article_count,
-           cluster_count: cluster_count,
-           url_tbl_off: url_ptr_pos,
-           title_tbl_off: title_ptr_pos,
-           cluster_tbl_off: cluster_ptr_pos,
-           mime_tbl_off: mime_list_pos,
-           main_page_idx: if main_page ==  0xffffffff { None } else { Some(main_page) },
-           layout_page_idx: if layout_page == 0xffffffffff { None } else { Some(layout_page) },
-           checksum_off: checksum_pos,
+/// Returns the path an entry should be extracted to under `dir`, or None if its url would
+/// escape `dir`
+fn extract_path(dir: &Path, entry: &DirectoryEntry) -> Option<PathBuf> {
+    let url = Path::new(&entry.url);
+    if url.components().any(|c| !matches!(c, Component::Normal(_))) {
+        return None;
+    }
+    let mut ns = String::new();
+    ns.push(entry.namespace);
+    Some(dir.join(ns).join(url))
+}
 
-           f: f,
-           master_view: master_view,
-           mime_table: mime_table,
-           url_list: url_list,
-           article_list: article_list,
-           cluster_list: cluster_list,
+/// Picks new paths for the files `extract_to_with_progress` can't write where `extract_path`
+/// puts them
+///
+/// `files` holds the namespace, url and path of each entry to be written, in url order.  An
+/// entry whose path is also a directory that other entries are written into (`A/Foo`, when
+/// there's an `A/Foo/Bar`), or that an earlier entry already has (`A/Foo/` and `A/Foo` both
+/// give `A/Foo`), is moved to the first free name with a `~1`, `~2`, ... suffix.  Only the
+/// entries that were moved are returned.
+fn renamed_extract_paths(files: &[(char, String, PathBuf)]) -> HashMap<(char, String), PathBuf> {
+    let mut dirs = HashSet::new();
+    for (_, _, path) in files {
+        dirs.extend(path.ancestors().skip(1));
+    }
 
-        })
+    let mut used = HashSet::new();
+    let mut renamed = HashMap::new();
+    for &(namespace, ref url, ref path) in files {
+        if !dirs.contains(path.as_path()) && used.insert(path.clone()) {
+            continue;
+        }
+        let mut n = 1;
+        let new_path = loop {
+            let mut name = path.file_name().map(|name| name.to_owned()).unwrap_or_default();
+            name.push(format!("~{}", n));
+            let candidate = path.with_file_name(name);
+            if !dirs.contains(candidate.as_path()) && !used.contains(&candidate) {
+                break candidate;
+            }
+            n += 1;
+        };
+        used.insert(new_path.clone());
+        renamed.insert((namespace, url.clone()), new_path);
+    }
+    renamed
+}
+
+/// Incremental XZ decoder over an in-memory buffer
+///
+/// Unlike `xz_decom::decompress`, this only decodes as much data as the caller reads, so we can
+/// stop as soon as we have the bytes we're after.
+struct XzReader<'a> {
+    state: *mut raw::xz_dec,
+    input: &'a [u8],
+    in_pos: usize,
+    finished: bool,
+}
 
+impl<'a> XzReader<'a> {
+    fn new(input: &'a [u8]) -> Result<XzReader<'a>, ParsingError> {
+        unsafe {
+            raw::xz_crc32_init();
+            raw::xz_crc64_init();
+        }
+        let state = unsafe { raw::xz_dec_init(raw::xz_mode::XZ_DYNALLOC, 1 << 26) };
+        if state.is_null() {
+            return Err(ParsingError::new(ParseErrorKind::Decompress, "Failed to initialize XZ decoder"));
+        }
+        Ok(XzReader {
+            state: state,
+            input: input,
+            in_pos: 0,
+            finished: false,
+        })
     }
+}
 
-    /// Indexes into the ZIM mime_table.  
-    pub fn get_mimetype(&self, id: u16) -> Option<MimeType> {
-        match id {
-            0xffff => Some(MimeType::Redirect),
-            0xfffe => Some(MimeType::LinkTarget),
-            0xfffd => Some(MimeType::DeletedEntry),
-            id => {
-                if (id as usize) < self.mime_table.len() {
-                     Some(MimeType::Type(self.mime_table[id as usize].clone()))
-                } else {
-                    println!("WARNINING unknown mimetype idx {}", id);
-                    None
+impl<'a> Read for XzReader<'a> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.finished || out.is_empty() {
+            return Ok(0);
+        }
+        let mut buf = raw::xz_buf {
+            _in: self.input.as_ptr(),
+            in_pos: self.in_pos as _,
+            in_size: self.input.len() as _,
+            out: out.as_mut_ptr(),
+            out_pos: 0,
+            out_size: out.len() as _,
+        };
+        loop {
+            let ret = unsafe { raw::xz_dec_run(self.state, &mut buf) };
+            self.in_pos = buf.in_pos as usize;
+            match ret {
+                raw::xz_ret::XZ_OK => {
+                    if buf.out_pos > 0 {
+                        return Ok(buf.out_pos as usize);
+                    }
+                    if self.in_pos == self.input.len() {
+                        return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                                  "Reached end of XZ input"));
+                    }
+                }
+                raw::xz_ret::XZ_STREAM_END => {
+                    self.finished = true;
+                    return Ok(buf.out_pos as usize);
+                }
+                e => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, raw::XZRawError::from(e)));
                 }
             }
         }
     }
+}
+
+impl<'a> Drop for XzReader<'a> {
+    fn drop(&mut self) {
+        unsafe { raw::xz_dec_end(self.state) };
+    }
+}
 
-    /// Iterates over articles, sorted by URL.
+/// Reads a whole cluster from a stream of decompressed cluster data
+///
+/// The last entry of the blob offset table is the size of the whole cluster, so once the table
+/// has been read, the rest of the data can go straight into a buffer of the right size.  This
+/// avoids the extra copies (and up to twice the memory) of growing a buffer as data arrives.
+#[cfg(not(feature = "liblzma"))]
+fn read_cluster_from<R: Read>(r: R, offset_size: u32) -> Result<Vec<u8>, ParsingError> {
+    let mut data = Vec::new();
+    try!(read_cluster_into(r, offset_size, &mut data));
+    Ok(data)
+}
+
+/// Like `read_cluster_from`, but reads into `data`, which is cleared first
+#[cfg(not(feature = "liblzma"))]
+fn read_cluster_into<R: Read>(mut r: R, offset_size: u32, data: &mut Vec<u8>) -> Result<(), ParsingError> {
+    use byteorder::WriteBytesExt;
+
+    // the first offset points just past the offset table, so it tells us how big the table is
+    let first_off = try!(read_blob_offset(&mut r, offset_size));
+    if first_off < offset_size || first_off % offset_size != 0 {
+        return Err(ParsingError::new(ParseErrorKind::OutOfRange, "Invalid blob offset table"));
+    }
+    let mut table = Vec::new();
+    try!(r.by_ref().take((first_off - offset_size) as u64).read_to_end(&mut table));
+    if table.len() < (first_off - offset_size) as usize {
+        return Err(ParsingError::new(ParseErrorKind::Truncated, "Blob offset table is past the end of the cluster"));
+    }
+    let total_len = if table.is_empty() {
+        first_off
+    } else {
+        try!(read_blob_offset(&mut Cursor::new(&table[table.len() - offset_size as usize..]), offset_size))
+    };
+    if total_len < first_off {
+        return Err(ParsingError::new(ParseErrorKind::OutOfRange, "Invalid blob offsets"));
+    }
+
+    // don't trust the offsets enough to reserve an unlimited amount up front
+    data.clear();
+    data.reserve(std::cmp::min(total_len as usize, 1 << 28));
+    if offset_size == 8 {
+        try!(data.write_u64::<LittleEndian>(first_off as u64));
+    } else {
+        try!(data.write_u32::<LittleEndian>(first_off));
+    }
+    data.extend_from_slice(&table);
+    try!(r.take((total_len - first_off) as u64).read_to_end(data));
+    if data.len() < total_len as usize {
+        return Err(ParsingError::new(ParseErrorKind::Truncated, "Blob is past the end of the cluster"));
+    }
+    Ok(())
+}
+
+/// Reads a single blob from a stream of (decompressed) cluster data
+///
+/// Only the blob offset table up to `idx` is kept; everything between the table and the start of
+/// the blob is discarded as it is read.
+fn read_blob_from<R: Read>(mut r: R, idx: u32, offset_size: u32) -> Result<Vec<u8>, ParsingError> {
+    // the first offset points just past the offset table, so it tells us how many offsets there are
+    let first_off = try!(read_blob_offset(&mut r, offset_size));
+    let num_offsets = first_off / offset_size;
+    if idx as u64 + 1 >= num_offsets as u64 {
+        return Err(ParsingError::new(ParseErrorKind::OutOfRange, "No such blob"));
+    }
+
+    let mut offsets = vec![first_off];
+    for _ in 0..idx + 1 {
+        offsets.push(try!(read_blob_offset(&mut r, offset_size)));
+    }
+    let this_blob_off = offsets[idx as usize] as u64;
+    let next_blob_off = offsets[idx as usize + 1] as u64;
+    let pos = offsets.len() as u64 * offset_size as u64;
+    if this_blob_off < pos || next_blob_off < this_blob_off {
+        return Err(ParsingError::new(ParseErrorKind::OutOfRange, "Invalid blob offsets"));
+    }
+
+    try!(io::copy(&mut r.by_ref().take(this_blob_off - pos), &mut io::sink()));
+    // don't trust the offsets enough to allocate the whole blob up front
+    let len = next_blob_off - this_blob_off;
+    let mut data = Vec::new();
+    try!(r.take(len).read_to_end(&mut data));
+    if (data.len() as u64) < len {
+        return Err(ParsingError::new(ParseErrorKind::Truncated, "Blob is past the end of the cluster"));
+    }
+    Ok(data)
+}
+
+/// Reads a NUL-terminated UTF-8 string
+fn read_c_string<R: BufRead>(r: &mut R) -> Result<String, ParsingError> {
+    let mut vec = Vec::new();
+    try!(r.read_until(0, &mut vec));
+    if vec.pop() != Some(0) {
+        return Err(ParsingError::new(ParseErrorKind::Truncated, "Unterminated string"));
+    }
+    Ok(try!(String::from_utf8(vec)))
+}
+
+/// Borrows the NUL-terminated UTF-8 string starting at `off` in `s`
+fn c_str_at(s: &[u8], off: usize) -> Result<&str, ParsingError> {
+    let rest = if off <= s.len() { &s[off..] } else { &[] };
+    match rest.iter().position(|&b| b == 0) {
+        Some(end) => Ok(try!(std::str::from_utf8(&rest[..end]))),
+        None => Err(ParsingError::new(ParseErrorKind::Truncated, "Unterminated string"))
+    }
+}
+
+/// Holds metadata about an article
+///
+/// Entries compare and sort by `sort_key`, (namespace, url), which is the order of the URL table.
+/// Within an archive that key is unique, so entries from the same archive are only equal if
+/// they're the same entry.
+#[derive(Debug, Clone)]
+pub struct DirectoryEntry {
+    pub mime_type: MimeType,
+    pub namespace: char,
+    /// The revision of the article's content
     ///
-    /// For performance reasons, you might want to extract by cluster instead.
-    pub fn iterate_by_urls(&self) -> DirectoryIterator {
-        DirectoryIterator::new(self)     
+    /// This is stored in the entry's header, but writers rarely set it, so it's usually 0.  It's
+    /// not part of what identifies an entry; two entries can't share a url even if their
+    /// revisions differ.
+    pub revision: u32,
+    pub url: String,
+    pub title: String,
+    pub target: Option<Target>,
+    encoded_len: usize,
+}
+
+impl DirectoryEntry {
+    fn new(zim: &Zim, s: &[u8]) -> Result<DirectoryEntry, ParsingError> {
+        DirectoryEntry::parse(s, |id| zim.get_mimetype(id))
     }
 
-    /// Returns the `DirectoryEntry` for the article found at the given URL index.
+    /// Parses a directory entry from its bytes, as returned by `Zim::raw_entry`
     ///
-    /// idx must be between 0 and `article_count`
-    pub fn get_by_url_index(&self, idx: u32) -> Option<DirectoryEntry> {
-        let entry_offset = self.url_list[idx as usize] as usize;
-        let dir_view = {
-            let mut view = unsafe{ self.master_view.clone() };
-            let len = view.len();
-            view.restrict(entry_offset, len - entry_offset);
-            view
+    /// `mime_lookup` turns the entry's mimetype id into a `MimeType`; with an open archive, that's
+    /// `|id| zim.get_mimetype(id)`.  It's only called for ids in the mime list range, since the
+    /// reserved ids for redirects, link targets, and deleted entries are handled here, and ids it
+    /// returns None for become `MimeType::Unknown`.  Trailing bytes after the title are ignored.
+    pub fn parse<F: Fn(u16) -> Option<MimeType>>(s: &[u8], mime_lookup: F) -> Result<DirectoryEntry, ParsingError> {
+        let mut cur = Cursor::new(s);
+        let mime_id = try!(cur.read_u16::<LittleEndian>());
+        // some archives have entries with ids past the end of the mime list; those are still
+        // readable, we just don't know what type they are
+        let mime_type = match mime_id {
+            0xffff => MimeType::Redirect,
+            0xfffe => MimeType::LinkTarget,
+            0xfffd => MimeType::DeletedEntry,
+            id => mime_lookup(id).unwrap_or(MimeType::Unknown(id))
         };
-        let slice = unsafe{ dir_view.as_slice() };
-        DirectoryEntry::new(self, slice).ok()
+        let _ = try!(cur.read_u8());
+        let namespace = try!(cur.read_u8());
+        let rev = try!(cur.read_u32::<LittleEndian>());
+        let mut target = None;
+
+
+        if mime_id == 0xffff {
+            // this is an index into the URL table
+            target = Some(Target::Redirect(try!(cur.read_u32::<LittleEndian>())));
+        } else if mime_id == 0xfffe || mime_id == 0xfffd {
+
+        } else {
+            let cluster_number = try!(cur.read_u32::<LittleEndian>());
+            let blob_number = try!(cur.read_u32::<LittleEndian>());
+            target = Some(Target::Cluster(cluster_number, blob_number));
+        }
+       
+        let url = try!(read_c_string(&mut cur));
+        let title = try!(read_c_string(&mut cur));
+
+
+        Ok(DirectoryEntry{
+            mime_type: mime_type,
+            namespace: std::char::from_u32(namespace as u32).unwrap(),
+            revision: rev,
+            url: url,
+            title: title,
+            target: target,
+            encoded_len: cur.position() as usize,
+        })
     }
 
-    /// Returns the given `Cluster`
-    /// 
-    /// idx must be between 0 and `cluster_count`
-    pub fn get_cluster(&self, idx: u32) -> Option<Cluster> {
-        Cluster::new(self, idx).ok()
+    /// Returns the revision of the article's content; see the `revision` field
+    pub fn revision(&self) -> u32 {
+        self.revision
+    }
+
+    /// Returns the number of bytes this entry takes up in the archive
+    ///
+    /// This covers the whole on-disk entry, including the mimetype, namespace, revision, target,
+    /// and both NUL-terminated strings, so the next entry in the file starts this many bytes
+    /// after this one.
+    pub fn encoded_len(&self) -> usize {
+        self.encoded_len
     }
 
+    /// Returns the key the URL table is sorted by: the namespace, then the url
+    pub fn sort_key(&self) -> (char, &str) {
+        (self.namespace, &self.url)
+    }
 }
 
+impl PartialEq for DirectoryEntry {
+    fn eq(&self, other: &DirectoryEntry) -> bool {
+        self.sort_key() == other.sort_key()
+    }
+}
 
+impl Eq for DirectoryEntry {}
 
-#[test]
-fn test_zim() {
+impl PartialOrd for DirectoryEntry {
+    fn partial_cmp(&self, other: &DirectoryEntry) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
 
-    // we want to handle all URLs from the same cluster at the same time,
-    // so build a map between cluster
-    // build a mapping from 
+impl Ord for DirectoryEntry {
+    fn cmp(&self, other: &DirectoryEntry) -> std::cmp::Ordering {
+        self.sort_key().cmp(&other.sort_key())
+    }
+}
 
-    //println!("{:?}", zim.get_by_url_index(59357));
+/// A directory entry that borrows its url and title from the archive
+///
+/// This is the lightweight counterpart to `DirectoryEntry`, for scans that only look at urls or
+/// titles: the strings are borrowed in place rather than copied, and the rest of the header is
+/// only decoded when asked for.  Use `to_entry` to get an owned `DirectoryEntry`.
+#[derive(Clone)]
+pub struct DirectoryEntryRef<'a> {
+    zim: &'a Zim,
+    data: &'a [u8],
+    url: &'a str,
+    title: &'a str,
+}
 
-    //let cluster = zim.get_cluster(201);
-    //let data = cluster.get_blob(6);
-    //let s = std::str::from_utf8(data).unwrap();
-    //println!("Cluster: {:?}", cluster);
-    //println!("data: {}", s);
+impl<'a> DirectoryEntryRef<'a> {
+    fn new(zim: &'a Zim, s: &'a [u8]) -> Result<DirectoryEntryRef<'a>, ParsingError> {
+        let mut cur = Cursor::new(s);
+        // the strings start after the target, whose size depends on the kind of entry
+        let header_len = match try!(cur.read_u16::<LittleEndian>()) {
+            0xffff => 12,
+            0xfffe | 0xfffd => 8,
+            _ => 16
+        };
+        let url = try!(c_str_at(s, header_len));
+        let title = try!(c_str_at(s, header_len + url.len() + 1));
+        Ok(DirectoryEntryRef {
+            zim: zim,
+            data: &s[..header_len + url.len() + title.len() + 2],
+            url: url,
+            title: title,
+        })
+    }
+
+    pub fn url(&self) -> &'a str {
+        self.url
+    }
+
+    pub fn title(&self) -> &'a str {
+        self.title
+    }
+
+    pub fn namespace(&self) -> char {
+        self.data[3] as char
+    }
+
+    /// Returns the mimetype id stored in the entry, without looking it up in the mime list
+    pub fn mime_id(&self) -> u16 {
+        Cursor::new(self.data).read_u16::<LittleEndian>().unwrap()
+    }
+
+    pub fn mime_type(&self) -> MimeType {
+        let mime_id = self.mime_id();
+        self.zim.get_mimetype(mime_id).unwrap_or(MimeType::Unknown(mime_id))
+    }
+
+    pub fn revision(&self) -> u32 {
+        Cursor::new(&self.data[4..]).read_u32::<LittleEndian>().unwrap()
+    }
+
+    pub fn target(&self) -> Option<Target> {
+        let mut cur = Cursor::new(&self.data[8..]);
+        match self.mime_id() {
+            0xffff => Some(Target::Redirect(cur.read_u32::<LittleEndian>().unwrap())),
+            0xfffe | 0xfffd => None,
+            _ => {
+                let cluster_number = cur.read_u32::<LittleEndian>().unwrap();
+                let blob_number = cur.read_u32::<LittleEndian>().unwrap();
+                Some(Target::Cluster(cluster_number, blob_number))
+            }
+        }
+    }
+
+    /// Returns the key the URL table is sorted by: the namespace, then the url
+    pub fn sort_key(&self) -> (char, &'a str) {
+        (self.namespace(), self.url)
+    }
+
+    /// Returns the number of bytes this entry takes up in the archive
+    pub fn encoded_len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Parses the whole entry into an owned `DirectoryEntry`
+    pub fn to_entry(&self) -> DirectoryEntry {
+        DirectoryEntry::new(self.zim, self.data).unwrap()
+    }
+}
+
+/// An entry's metadata without its content, for building catalogs
+///
+/// See `Zim::entry_info`.  Unlike `DirectoryEntry`, the mimetype is kept as its id; use
+/// `Zim::get_mimetype` to look it up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryInfo {
+    pub namespace: char,
+    pub url: String,
+    pub title: String,
+    pub mime_id: u16,
+    pub is_redirect: bool,
+}
+
+impl<'a> std::fmt::Debug for DirectoryEntryRef<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("DirectoryEntryRef")
+            .field("namespace", &self.namespace())
+            .field("url", &self.url)
+            .field("title", &self.title)
+            .finish()
+    }
+}
+
+/// Options controlling how a ZIM file is opened
+///
+/// The defaults match `Zim::new`: no extra validation and no cluster caching.
+///
+/// ```ignore
+/// let zim = Zim::open_with("wikipedia.zim", ZimOptions::new().validate_ranges(true).cluster_cache_size(16));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ZimOptions {
+    validate_checksum: bool,
+    validate_ranges: bool,
+    cluster_cache_size: usize,
+    entry_cache_size: usize,
+    lazy_tables: bool,
+    buffered: bool,
+    validate_clusters: bool,
+    on_decompress: Option<DecompressHook>,
+}
+
+/// Details of a cluster decompression, as passed to `ZimOptions::on_decompress`
+#[derive(Debug, Clone)]
+pub struct DecompressInfo {
+    /// The index of the cluster
+    pub cluster_idx: u32,
+    /// The size of the cluster as stored in the archive, including the compression type byte
+    pub compressed_len: u64,
+    /// The size of the decompressed cluster
+    pub decompressed_len: usize,
+    /// How long decompressing took
+    pub duration: Duration,
+}
+
+/// The callback set with `ZimOptions::on_decompress`
+#[derive(Clone)]
+struct DecompressHook(Arc<dyn Fn(&DecompressInfo) + Send + Sync>);
+
+impl std::fmt::Debug for DecompressHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "DecompressHook")
+    }
+}
+
+impl ZimOptions {
+    pub fn new() -> ZimOptions {
+        ZimOptions::default()
+    }
+
+    /// Verify the MD5 checksum at the end of the file when opening it
+    ///
+    /// This reads the entire file, so it's slow for large archives.  Archives without a checksum
+    /// (a checksum offset of 0 in the header) have nothing to verify, and open as if this were
+    /// off.
+    pub fn validate_checksum(mut self, validate: bool) -> ZimOptions {
+        self.validate_checksum = validate;
+        self
+    }
+
+    /// Check that the offset tables and every offset in them point inside the file when opening it
+    pub fn validate_ranges(mut self, validate: bool) -> ZimOptions {
+        self.validate_ranges = validate;
+        self
+    }
+
+    /// Check the compression type byte of every cluster when opening the file
+    ///
+    /// This reads one byte per cluster without decompressing anything, so it's a cheap way to
+    /// catch a badly corrupted cluster table.  Opening fails if any cluster has a compression
+    /// type this crate doesn't know, or one it can't decompress (zstd).
+    pub fn validate_clusters(mut self, validate: bool) -> ZimOptions {
+        self.validate_clusters = validate;
+        self
+    }
+
+    /// Read entries from the url, title, and cluster offset tables straight out of the memory map
+    /// when they're needed, instead of copying the tables into memory when opening the file
+    ///
+    /// This makes opening very large archives much faster and saves memory, at the cost of
+    /// slightly slower lookups.
+    pub fn lazy_tables(mut self, lazy: bool) -> ZimOptions {
+        self.lazy_tables = lazy;
+        self
+    }
+
+    /// Read the whole file into memory when opening it, instead of memory-mapping it
+    ///
+    /// This is for platforms and filesystems where mmap isn't available or doesn't work well.
+    /// The entire archive is held in memory for as long as the `Zim` is open, so this is only
+    /// sensible for small to medium archives; with mmap, the OS only keeps the parts of the file
+    /// that are actually being read in memory.  `lazy_tables` is recommended alongside this, since
+    /// the offset tables are already in memory.
+    pub fn buffered(mut self, buffered: bool) -> ZimOptions {
+        self.buffered = buffered;
+        self
+    }
+
+    /// Keep up to `size` of the most recently used decompressed clusters in memory
+    ///
+    /// Cached clusters are returned by `Zim::get_cluster` without being decompressed again.  The
+    /// default of 0 disables the cache.
+    pub fn cluster_cache_size(mut self, size: usize) -> ZimOptions {
+        self.cluster_cache_size = size;
+        self
+    }
+
+    /// Keep up to `size` of the most recently used directory entries in memory
+    ///
+    /// Cached entries are returned by `Zim::get_by_url_index` without being parsed again, which
+    /// helps when the same entries are looked up over and over (for example, when resolving the
+    /// links in a page).  The default of 0 disables the cache.
+    pub fn entry_cache_size(mut self, size: usize) -> ZimOptions {
+        self.entry_cache_size = size;
+        self
+    }
+
+    /// Call `hook` each time a whole compressed cluster is decompressed, with how long it took
+    ///
+    /// This is meant for tuning `cluster_cache_size` and finding expensive clusters.  Clusters
+    /// read from the cache, uncompressed clusters, and single blobs read with
+    /// `Zim::read_blob_partial` aren't reported.  When no hook is set, nothing is timed.
+    pub fn on_decompress<F: Fn(&DecompressInfo) + Send + Sync + 'static>(mut self, hook: F) -> ZimOptions {
+        self.on_decompress = Some(DecompressHook(Arc::new(hook)));
+        self
+    }
+}
+
+/// A small least-recently-used cache, keyed by cluster or url index
+struct LruCache<T> {
+    capacity: usize,
+    items: RefCell<VecDeque<(u32, T)>>, // most recently used first
+}
+
+impl<T: Clone> LruCache<T> {
+    fn new(capacity: usize) -> LruCache<T> {
+        LruCache {
+            capacity: capacity,
+            items: RefCell::new(VecDeque::new()),
+        }
+    }
+
+    fn get(&self, key: u32) -> Option<T> {
+        let mut items = self.items.borrow_mut();
+        let pos = items.iter().position(|&(k, _)| k == key)?;
+        let hit = items.remove(pos).unwrap();
+        let value = hit.1.clone();
+        items.push_front(hit);
+        Some(value)
+    }
+
+    fn insert(&self, key: u32, value: &T) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut items = self.items.borrow_mut();
+        items.truncate(self.capacity - 1);
+        items.push_front((key, value.clone()));
+    }
+}
+
+/// Major versions of the ZIM format that this crate can read
+const SUPPORTED_VERSIONS: [u16; 2] = [5, 6];
+
+/// Expected access pattern, for `Zim::advise`
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Advice {
+    Normal,
+    Sequential,
+    Random,
+}
+
+/// Advises sequential access for as long as it's alive, then puts back the advice that was in
+/// effect before
+///
+/// Bulk reads hold one of these, so that reading a whole archive doesn't leave readahead turned
+/// up for the scattered reads that come after it.
+struct SequentialAdvice<'a> {
+    zim: &'a Zim,
+    previous: Advice,
+}
+
+impl<'a> SequentialAdvice<'a> {
+    fn new(zim: &'a Zim) -> SequentialAdvice<'a> {
+        let previous = zim.advice.get();
+        zim.advise(Advice::Sequential);
+        SequentialAdvice { zim: zim, previous: previous }
+    }
+}
+
+impl<'a> Drop for SequentialAdvice<'a> {
+    fn drop(&mut self) {
+        self.zim.advise(self.previous);
+    }
+}
+
+/// Represents a ZIM file
+///
+/// The file is closed as soon as it has been mapped (or, with `ZimOptions::buffered`, read), so
+/// the memory map is the only thing holding on to it; dropping the `Zim` unmaps it.  On Windows,
+/// where a mapped file can't be deleted or overwritten, this means the file is free again once
+/// the `Zim` (and anything borrowing from it) is dropped.
+#[allow(dead_code)]
+pub struct Zim {
+    // Zim structure data:
+
+    version: u16,
+    minor_version: u16,
+    // uuid_1
+    // uuid_2
+    /// Number of articles in this archive
+    pub article_count: u32,
+    /// Number of clusters in this archive
+    pub cluster_count: u32,
+    url_tbl_off: u64, //offset from the start of the file
+    title_tbl_off: u64, //offset from the start of the file
+    cluster_tbl_off: u64,
+    mime_tbl_off: u64, // usually 80, right after the header
+    /// If Main Page is defined, this is the index to the page
+    pub main_page_idx: Option<u32>, // an index into the url table
+    layout_page_idx: Option<u32>,
+    checksum_off: u64,
+
+    // internal variables:
+    path: PathBuf, // the path the archive was opened from, for `reopen`
+    storage: Storage,
+
+    /// List of mimetypes used in this ZIM archive
+    mime_table: Vec<String>, // a list of mimetypes
+    // these are left empty when `lazy_tables` is set; use url_offset, title_entry, and
+    // cluster_offset instead of reading them directly
+    url_list: Vec<u64>, // a list of offsets
+    article_list: Vec<u32>, // a list of indicies into url_list
+    cluster_list: Vec<u64>, // a list of offsets
+
+    options: ZimOptions,
+    cluster_cache: LruCache<Cluster>,
+    entry_cache: LruCache<DirectoryEntry>,
+    advice: Cell<Advice>, // the last advice given, so bulk reads can put it back
+}
+
+pub struct DirectoryIterator<'a> {
+    max_articles: u32, // one past the last article to yield from the back
+    article_to_yield: u32,
+    zim: &'a Zim
+}
+
+impl<'a> DirectoryIterator<'a> {
+    fn new(zim: &'a Zim) -> DirectoryIterator<'a> {
+        DirectoryIterator {
+            max_articles: zim.article_count,
+            article_to_yield: 0,
+            zim: zim
+        }
+    }
+}
+
+impl<'a> std::iter::Iterator for DirectoryIterator<'a> {
+    type Item = DirectoryEntry;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.article_to_yield >= self.max_articles {
+            None 
+        } else {
+            let idx = self.article_to_yield;
+            self.article_to_yield += 1;
+            self.zim.read_entry(idx).ok()
+        }
+    }
+}
+
+impl<'a> std::iter::DoubleEndedIterator for DirectoryIterator<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.article_to_yield >= self.max_articles {
+            None
+        } else {
+            self.max_articles -= 1;
+            self.zim.read_entry(self.max_articles).ok()
+        }
+    }
+}
+
+/// Iterates over articles sorted by namespace and title
+///
+/// See `Zim::iterate_by_titles`
+pub struct TitleIterator<'a> {
+    end: u32, // one past the last title index to yield from the back
+    next: u32,
+    zim: &'a Zim
+}
+
+impl<'a> std::iter::Iterator for TitleIterator<'a> {
+    type Item = Result<DirectoryEntry, ParsingError>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next >= self.end {
+            None
+        } else {
+            let idx = self.next;
+            self.next += 1;
+            Some(self.zim.read_entry(self.zim.title_entry(idx)))
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = (self.end - self.next) as usize;
+        (len, Some(len))
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        // skip straight to the entry, without reading the ones in between
+        let remaining = (self.end - self.next) as usize;
+        self.next = if n < remaining { self.next + n as u32 } else { self.end };
+        self.next()
+    }
+}
+
+impl<'a> std::iter::ExactSizeIterator for TitleIterator<'a> {}
+
+impl<'a> std::iter::DoubleEndedIterator for TitleIterator<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.next >= self.end {
+            None
+        } else {
+            self.end -= 1;
+            Some(self.zim.read_entry(self.zim.title_entry(self.end)))
+        }
+    }
+}
+
+/// Iterates over articles sorted by URL, along with their URL indices
+///
+/// See `Zim::iter_by_urls_enumerated`
+pub struct EnumeratedDirectoryIterator<'a> {
+    inner: DirectoryIterator<'a>
+}
+
+impl<'a> std::iter::Iterator for EnumeratedDirectoryIterator<'a> {
+    type Item = (u32, DirectoryEntry);
+    fn next(&mut self) -> Option<Self::Item> {
+        let idx = self.inner.article_to_yield;
+        self.inner.next().map(|entry| (idx, entry))
+    }
+}
+
+impl<'a> std::iter::DoubleEndedIterator for EnumeratedDirectoryIterator<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(|entry| (self.inner.max_articles, entry))
+    }
+}
+
+/// Iterates over articles sorted by URL, borrowing their urls and titles from the archive
+///
+/// See `Zim::iterate_by_urls_ref`
+pub struct DirectoryRefIterator<'a> {
+    inner: DirectoryIterator<'a>
+}
+
+impl<'a> std::iter::Iterator for DirectoryRefIterator<'a> {
+    type Item = DirectoryEntryRef<'a>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.inner.article_to_yield >= self.inner.max_articles {
+            None
+        } else {
+            let idx = self.inner.article_to_yield;
+            self.inner.article_to_yield += 1;
+            self.inner.zim.read_entry_ref(idx).ok()
+        }
+    }
+}
+
+impl<'a> std::iter::DoubleEndedIterator for DirectoryRefIterator<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.inner.article_to_yield >= self.inner.max_articles {
+            None
+        } else {
+            self.inner.max_articles -= 1;
+            self.inner.zim.read_entry_ref(self.inner.max_articles).ok()
+        }
+    }
+}
+
+/// Iterates over the urls of the entries in a namespace, in url order
+///
+/// See `Zim::urls_in_namespace`
+pub struct NamespaceUrlIterator<'a> {
+    zim: &'a Zim,
+    next: u32,
+    end: u32,
+}
+
+impl<'a> std::iter::Iterator for NamespaceUrlIterator<'a> {
+    type Item = String;
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.next < self.end {
+            let idx = self.next;
+            self.next += 1;
+            if let Ok((_, _, url)) = self.zim.read_entry_url(idx) {
+                return Some(url.to_owned());
+            }
+        }
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some((self.end - self.next) as usize))
+    }
+}
+
+/// Iterates over the link target entries, sorted by URL
+///
+/// See `Zim::link_targets`
+pub struct LinkTargetIterator<'a> {
+    inner: DirectoryIterator<'a>
+}
+
+impl<'a> std::iter::Iterator for LinkTargetIterator<'a> {
+    type Item = DirectoryEntry;
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.inner.article_to_yield < self.inner.max_articles {
+            let idx = self.inner.article_to_yield;
+            self.inner.article_to_yield += 1;
+            // only the mimetype id is checked, so other entries aren't copied out
+            if let Ok(entry) = self.inner.zim.read_entry_ref(idx) {
+                if entry.mime_id() == 0xfffe {
+                    return Some(entry.to_entry());
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Iterates over the namespace, url, and mimetype of every entry, sorted by URL
+///
+/// See `Zim::index_entries`
+pub struct IndexEntryIterator<'a> {
+    inner: DirectoryIterator<'a>
+}
+
+impl<'a> std::iter::Iterator for IndexEntryIterator<'a> {
+    type Item = (char, String, MimeType);
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.inner.article_to_yield >= self.inner.max_articles {
+            None
+        } else {
+            let idx = self.inner.article_to_yield;
+            self.inner.article_to_yield += 1;
+            self.inner.zim.read_index_entry(idx).ok()
+        }
+    }
+}
+
+/// Iterates over articles and their data, in cluster order
+///
+/// See `Zim::iter_article_content`
+pub struct ArticleContentIterator<'a> {
+    zim: &'a Zim,
+    clusters: std::vec::IntoIter<(u32, Vec<DirectoryEntry>)>,
+    current: Option<(Cluster, std::vec::IntoIter<DirectoryEntry>)>,
+    _advice: SequentialAdvice<'a>,
+}
+
+impl<'a> std::iter::Iterator for ArticleContentIterator<'a> {
+    type Item = Result<(DirectoryEntry, Vec<u8>), ParsingError>;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((ref cluster, ref mut entries)) = self.current {
+                if let Some(entry) = entries.next() {
+                    let data = match entry.target {
+                        Some(Target::Cluster(_, bid)) => cluster.get_blob_checked(bid).map(Vec::from),
+                        _ => unreachable!()
+                    };
+                    return Some(data.map(|data| (entry, data)));
+                }
+            }
+            let (cid, entries) = self.clusters.next()?;
+            match self.zim.cached_cluster(cid) {
+                Ok(cluster) => self.current = Some((cluster, entries.into_iter())),
+                Err(e) => {
+                    self.current = None;
+                    self.clusters = Vec::new().into_iter();
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+/// Iterates over runs of consecutive entries that share a cluster, in url order
+///
+/// See `Zim::iter_grouped_by_cluster`
+pub struct ClusterGroupIterator<'a> {
+    entries: DirectoryIterator<'a>,
+    // the first entry of the next group, read while finishing the previous one
+    pending: Option<(u32, u32, DirectoryEntry)>,
+}
+
+impl<'a> std::iter::Iterator for ClusterGroupIterator<'a> {
+    type Item = (u32, Vec<(u32, DirectoryEntry)>);
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut group: Option<(u32, Vec<(u32, DirectoryEntry)>)> =
+            self.pending.take().map(|(cid, bid, entry)| (cid, vec![(bid, entry)]));
+        for entry in &mut self.entries {
+            let (cid, bid) = match entry.target {
+                Some(Target::Cluster(cid, bid)) => (cid, bid),
+                _ => continue
+            };
+            match group {
+                Some((group_cid, ref mut blobs)) if group_cid == cid => blobs.push((bid, entry)),
+                Some(_) => {
+                    self.pending = Some((cid, bid, entry));
+                    break;
+                }
+                None => group = Some((cid, vec![(bid, entry)])),
+            }
+        }
+        group
+    }
+}
+
+/// Iterates over the metadata entries of an archive, as (key, value) pairs
+///
+/// See `Zim::metadata_entries`
+pub struct MetadataIterator<'a> {
+    zim: &'a Zim,
+    next: u32,
+    end: u32,
+}
+
+impl<'a> std::iter::Iterator for MetadataIterator<'a> {
+    type Item = (String, Vec<u8>);
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.next < self.end {
+            let idx = self.next;
+            self.next += 1;
+            let entry = match self.zim.get_by_url_index(idx) {
+                Some(entry) => entry,
+                None => continue
+            };
+            let data = self.zim.resolve_entry(&entry).and_then(|target| self.zim.read_blob(&target));
+            if let Ok(blob) = data {
+                return Some((entry.url, blob.data));
+            }
+        }
+        None
+    }
+}
+
+/// A prebuilt map from (namespace, url) to URL index
+///
+/// See `Zim::build_url_index`
+pub struct UrlIndex {
+    map: HashMap<(char, String), u32>
+}
+
+impl UrlIndex {
+    /// Number of urls in this index
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns true if this index has no urls
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+/// The differences between the `A` namespaces of two archives
+///
+/// See `Zim::diff`.  Each list holds urls (without the namespace), in sorted order.
+#[cfg(feature = "sha2")]
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ArchiveDiff {
+    /// Urls only in the other archive
+    pub added: Vec<String>,
+    /// Urls only in this archive
+    pub removed: Vec<String>,
+    /// Urls in both archives whose data or redirect target differ
+    pub changed: Vec<String>,
+}
+
+#[cfg(feature = "sha2")]
+impl ArchiveDiff {
+    /// Returns true if the two archives had the same articles
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// What an entry holds, for comparing entries across archives
+#[cfg(feature = "sha2")]
+#[derive(PartialEq)]
+enum EntryFingerprint {
+    /// The SHA-256 digest of the entry's data
+    Data([u8; 32]),
+    /// The namespace and url the entry redirects to
+    Redirect(char, String),
+    /// A link target or deleted entry
+    NoData,
+    /// The entry's data or redirect couldn't be read; this never matches anything
+    Unreadable,
+}
+
+impl Zim {
+    /// Loads a Zim file
+    ///
+    /// Loads a Zim file and parses the header, and the url, title, and cluster offset tables.  The
+    /// rest of the data isn't parsed until it's needed, so this should be fairly quick.
+    pub fn new<P: AsRef<Path>>(p: P) -> Result<Zim, ParsingError> {
+        Zim::open_with(p, ZimOptions::new())
+    }
+
+    /// Loads a Zim file by reading the whole file into memory, rather than memory-mapping it
+    ///
+    /// See `ZimOptions::buffered`.
+    pub fn open_buffered<P: AsRef<Path>>(p: P) -> Result<Zim, ParsingError> {
+        Zim::open_with(p, ZimOptions::new().buffered(true))
+    }
+
+    /// Loads a Zim file, using the given options
+    ///
+    /// See `ZimOptions` for what can be configured.
+    pub fn open_with<P: AsRef<Path>>(p: P, options: ZimOptions) -> Result<Zim, ParsingError> {
+        let path = p.as_ref().to_path_buf();
+        // the map keeps its own handle to the file, so ours is closed at the end of this block
+        let storage = {
+            let mut f = try!(File::open(&path));
+            if options.buffered {
+                let mut data = Vec::new();
+                try!(f.read_to_end(&mut data));
+                Storage::Buffered(data)
+            } else {
+                Storage::Mapped(try!(Mmap::open(&f, memmap::Protection::Read)).into_view())
+            }
+        };
+        Zim::from_storage(storage, path, options)
+    }
+
+    /// Opens an archive on a web server, reading it with HTTP range requests
+    ///
+    /// See `open_url_with`.
+    #[cfg(feature = "remote")]
+    pub fn open_url(url: &str) -> Result<Zim, ParsingError> {
+        Zim::open_url_with(url, ZimOptions::new())
+    }
+
+    /// Opens an archive on a web server, using the given options
+    ///
+    /// Only the header and tables are fetched up front (a few requests, or fewer with
+    /// `ZimOptions::lazy_tables`); directory entries and clusters are fetched as they're read.
+    /// Everything fetched is kept in memory until the `Zim` is dropped, so reading a cluster
+    /// again doesn't fetch it again.  `ZimOptions::buffered` doesn't apply, and
+    /// `ZimOptions::validate_checksum` downloads the whole file.
+    ///
+    /// The server has to support range requests; if it answers with the whole file instead,
+    /// this fails with an `Io` error saying so, rather than downloading it.  Directory entries
+    /// longer than 8KB can't be read this way.
+    #[cfg(feature = "remote")]
+    pub fn open_url_with(url: &str, options: ZimOptions) -> Result<Zim, ParsingError> {
+        let storage = Storage::Remote(try!(remote::RemoteStorage::open(url)));
+        Zim::from_storage(storage, PathBuf::new(), options)
+    }
+
+    /// Parses the header and tables of an archive, wherever its bytes come from
+    fn from_storage(storage: Storage, path: PathBuf, options: ZimOptions) -> Result<Zim, ParsingError> {
+        let mut header_cur = Cursor::new(try!(storage.region(0, 80)));
+
+        let magic = try!(header_cur.read_u32::<LittleEndian>());
+        if magic != 72173914 {
+            return Err(ParsingError::new(ParseErrorKind::BadMagic, "Not a ZIM file"));
+        }
+        let version = try!(header_cur.read_u16::<LittleEndian>());
+        let minor_version = try!(header_cur.read_u16::<LittleEndian>());
+        if !SUPPORTED_VERSIONS.contains(&version) {
+            return Err(ParsingError{
+                cause: Some(From::from(format!("ZIM major version {} is not supported", version))),
+                ..ParsingError::new(ParseErrorKind::UnsupportedVersion, "Unsupported ZIM version").at_offset(4)
+            });
+        }
+        let uuid_1 = try!(header_cur.read_u64::<LittleEndian>());
+        let uuid_2 = try!(header_cur.read_u64::<LittleEndian>());
+        let article_count = try!(header_cur.read_u32::<LittleEndian>());
+        let cluster_count = try!(header_cur.read_u32::<LittleEndian>());
+        let url_ptr_pos = try!(header_cur.read_u64::<LittleEndian>());
+        let title_ptr_pos = try!(header_cur.read_u64::<LittleEndian>());
+        let cluster_ptr_pos = try!(header_cur.read_u64::<LittleEndian>());
+        let mime_list_pos = try!(header_cur.read_u64::<LittleEndian>());
+        let main_page = try!(header_cur.read_u32::<LittleEndian>());
+        let layout_page = try!(header_cur.read_u32::<LittleEndian>());
+        let checksum_pos = try!(header_cur.read_u64::<LittleEndian>());
+        debug_assert_eq!(header_cur.position(), 80);
+
+        // the mime table is usually directly after the 80-byte header, but we go by the offset in
+        // the header rather than relying on that
+        if mime_list_pos < 80 || mime_list_pos >= storage.len() as u64 {
+            return Err(ParsingError::new(ParseErrorKind::OutOfRange, "Mime list offset is out of range").at_offset(56));
+        }
+        let mime_table = {
+            let mut mime_cur = Cursor::new(try!(storage.region(try!(to_usize(mime_list_pos)), usize::MAX)));
+            let mut mime_table = Vec::new();
+            loop {
+                let mut mime_buf = Vec::new();
+                if let Ok(size) = mime_cur.read_until(0, &mut mime_buf) {
+                    if size <= 1 { break; }
+                    mime_buf.truncate(size - 1);
+                    mime_table.push(try!(String::from_utf8(mime_buf)));
+                }
+            }
+            mime_table
+        };
+
+        // a corrupt article or cluster count would otherwise have us reading (and, without lazy
+        // tables, allocating) far past the end of the file
+        let file_len = storage.len() as u64;
+        let tables = [(url_ptr_pos, article_count as u64 * 8, 24, "Article count doesn't fit in the file"),
+                      (title_ptr_pos, article_count as u64 * 4, 24, "Article count doesn't fit in the file"),
+                      (cluster_ptr_pos, cluster_count as u64 * 8, 28, "Cluster count doesn't fit in the file")];
+        for &(off, len, count_off, msg) in &tables {
+            if off.checked_add(len).is_none_or(|end| end > file_len) {
+                return Err(ParsingError::new(ParseErrorKind::OutOfRange, msg).at_offset(count_off));
+            }
+        }
+
+        // with lazy tables, we don't copy anything out of the offset tables here
+        let (table_articles, table_clusters) = if options.lazy_tables {
+            (0, 0)
+        } else {
+            (article_count, cluster_count)
+        };
+
+        let url_list = {
+            let mut list = Vec::new();
+            let mut url_cur = Cursor::new(try!(storage.region(try!(to_usize(url_ptr_pos)), (article_count as usize).saturating_mul(8))));
+
+            for url_num in 0..table_articles {
+                let pointer = try!(url_cur.read_u64::<LittleEndian>());
+                list.push(pointer);
+            }
+            list
+        };
+        
+        let article_list = {
+            let mut list = Vec::new();
+            let mut art_cur = Cursor::new(try!(storage.region(try!(to_usize(title_ptr_pos)), (article_count as usize).saturating_mul(4))));
+
+            for _ in 0..table_articles {
+                let url_number = try!(art_cur.read_u32::<LittleEndian>());
+                list.push(url_number);
+            }
+            list
+        };
+
+
+        let cluster_list = {
+            let mut list = Vec::new();
+            let mut cluster_cur = Cursor::new(try!(storage.region(try!(to_usize(cluster_ptr_pos)), (cluster_count as usize).saturating_mul(8))));
+
+            for cluster_num in 0..table_clusters {
+                let pointer = try!(cluster_cur.read_u64::<LittleEndian>());
+                list.push(pointer);
+            }
+            list
+        };
+
+
+        
+        let zim = Zim {
+           version: version,
+           minor_version: minor_version,
+           article_count: article_count,
+           cluster_count: cluster_count,
+           url_tbl_off: url_ptr_pos,
+           title_tbl_off: title_ptr_pos,
+           cluster_tbl_off: cluster_ptr_pos,
+           mime_tbl_off: mime_list_pos,
+           main_page_idx: if main_page ==  0xffffffff { None } else { Some(main_page) },
+           layout_page_idx: if layout_page == 0xffffffff { None } else { Some(layout_page) },
+           checksum_off: checksum_pos,
+
+           path: path,
+           storage: storage,
+           mime_table: mime_table,
+           url_list: url_list,
+           article_list: article_list,
+           cluster_list: cluster_list,
+
+           options: options.clone(),
+           cluster_cache: LruCache::new(options.cluster_cache_size),
+           entry_cache: LruCache::new(options.entry_cache_size),
+           advice: Cell::new(Advice::Normal),
+        };
+
+        if zim.options.validate_ranges {
+            try!(zim.check_ranges());
+        }
+        if zim.options.validate_clusters {
+            try!(zim.check_cluster_types());
+        }
+        if zim.options.validate_checksum && zim.has_checksum() && !zim.checksum_matches() {
+            return Err(ParsingError::new(ParseErrorKind::ChecksumMismatch, "Checksum mismatch"));
+        }
+
+        Ok(zim)
+    }
+
+    /// Loads a Zim file that might be malformed, checking everything that can be checked up front
+    ///
+    /// On top of the checks done by `ZimOptions::validate_ranges`, this parses every directory
+    /// entry, checks that each one points at a cluster or entry that exists, and checks the blob
+    /// offset table of every uncompressed cluster.  Compressed clusters would have to be
+    /// decompressed to check them, so their blob offset tables, and whether each entry's blob
+    /// index is within its cluster, are only checked when they're read.
+    ///
+    /// Passing these checks doesn't mean every article can be read, but whatever is in the file,
+    /// this returns a `ParsingError` rather than panicking, and so do the other methods of the
+    /// returned `Zim`, including bulk ones like `extract_to` and `iter_article_content`.  The
+    /// exceptions are methods like `Cluster::get_blob` that are documented to panic on a bad
+    /// index; use their `_checked` versions with untrusted archives.  This reads every directory
+    /// entry, so it's slower than `Zim::new` for large archives.
+    pub fn open_checked<P: AsRef<Path>>(p: P) -> Result<Zim, ParsingError> {
+        let zim = try!(Zim::open_with(p, ZimOptions::new().validate_ranges(true)));
+        try!(zim.check_entries());
+        try!(zim.check_clusters());
+        Ok(zim)
+    }
+
+    /// Opens the same file again, giving an independent `Zim` with the same options
+    ///
+    /// The new handle has its own (empty) caches, so several of them don't contend on a shared
+    /// one.  Reopening is much cheaper than opening the file cold, since its header and tables
+    /// are already in the OS page cache.  The file is opened by path, so if it has been replaced
+    /// since this `Zim` was opened, the new handle reads the new file.
+    pub fn reopen(&self) -> Result<Zim, ParsingError> {
+        #[cfg(feature = "remote")]
+        {
+            if let Storage::Remote(ref remote) = self.storage {
+                return Zim::open_url_with(&remote.url, self.options.clone());
+            }
+        }
+        Zim::open_with(&self.path, self.options.clone())
+    }
+
+    /// The number of entries in this archive (the same as `article_count`)
+    pub fn len(&self) -> usize {
+        self.article_count as usize
+    }
+
+    /// Returns true if this archive has no entries at all
+    pub fn is_empty(&self) -> bool {
+        self.article_count == 0
+    }
+
+    /// The major version of the ZIM format used by this archive
+    ///
+    /// Versions 5 and 6 are supported; opening an archive with any other major version fails.
+    pub fn version(&self) -> u32 {
+        self.version as u32
+    }
+
+    /// The minor version of the ZIM format used by this archive
+    pub fn minor_version(&self) -> u32 {
+        self.minor_version as u32
+    }
+
+    /// Returns the 80-byte header at the start of the file, exactly as stored
+    ///
+    /// This is mainly useful when reporting a problem with an archive, since the header can be
+    /// shared without the rest of the (possibly huge) file.  It can only be shorter than 80 bytes
+    /// if the file was truncated after it was opened.
+    pub fn raw_header(&self) -> &[u8] {
+        // the header was read when the archive was opened, so for remote archives it's cached
+        self.region(0, 80).unwrap_or(&[])
+    }
+
+    /// The size of the archive file, in bytes
+    pub fn file_len(&self) -> u64 {
+        self.storage.len() as u64
+    }
+
+    /// The file offset where the archive's data ends and the 16-byte MD5 checksum begins
+    ///
+    /// Everything before this offset (the header, tables, directory entries, and clusters) is
+    /// covered by the checksum.  In a well-formed archive, `data_end() + 16 == file_len()`.  Some
+    /// archives are written without a checksum, with a checksum offset of 0 in the header; for
+    /// those, the data runs to the end of the file and this is the same as `file_len()`.
+    pub fn data_end(&self) -> u64 {
+        if self.has_checksum() {
+            self.checksum_off
+        } else {
+            self.file_len()
+        }
+    }
+
+    /// Returns true if the archive has an MD5 checksum at the end
+    fn has_checksum(&self) -> bool {
+        self.checksum_off != 0
+    }
+
+    /// Indexes into the ZIM mime_table.  
+    ///
+    /// The ids 0xffff, 0xfffe, and 0xfffd are reserved for redirects, link targets, and deleted
+    /// entries.  Any other id is an index into the archive's mime list, and ids past the end of
+    /// it (including ones just below the reserved range, like 0xfffc) give `MimeType::Unknown`,
+    /// so this currently always returns `Some`.
+    pub fn get_mimetype(&self, id: u16) -> Option<MimeType> {
+        match id {
+            0xffff => Some(MimeType::Redirect),
+            0xfffe => Some(MimeType::LinkTarget),
+            0xfffd => Some(MimeType::DeletedEntry),
+            id => {
+                if (id as usize) < self.mime_table.len() {
+                     Some(MimeType::Type(self.mime_table[id as usize].clone()))
+                } else {
+                    Some(MimeType::Unknown(id))
+                }
+            }
+        }
+    }
+
+    /// Returns the number of mimetypes in the archive's mime list
+    ///
+    /// This doesn't count the reserved ids for redirects, link targets, and deleted entries, so
+    /// the valid ids for `get_mimetype` to look up in the list are `0..mime_type_count()`.
+    pub fn mime_type_count(&self) -> usize {
+        self.mime_table.len()
+    }
+
+    /// Iterates over articles, sorted by URL.
+    ///
+    /// For performance reasons, you might want to extract by cluster instead.
+    pub fn iterate_by_urls(&self) -> DirectoryIterator<'_> {
+        DirectoryIterator::new(self)     
+    }
+
+    /// Iterates over articles sorted by namespace and title
+    ///
+    /// The iterator knows its length and can be read from both ends, and `nth` jumps straight to
+    /// an entry, so a page of titles can be read without going through the ones before it.  An
+    /// entry that can't be parsed is yielded as an error rather than ending the iteration, so the
+    /// length always matches the number of items.
+    pub fn iterate_by_titles(&self) -> TitleIterator<'_> {
+        TitleIterator {
+            end: self.article_count,
+            next: 0,
+            zim: self
+        }
+    }
+
+    /// Returns the URL indices of all the entries, sorted by namespace and title
+    ///
+    /// This is the archive's title table, which can be binary searched to look up entries by
+    /// title.  It's borrowed from the copy made when the archive was opened, unless the archive
+    /// was opened with `lazy_tables`, in which case the whole table is read from the file on each
+    /// call.
+    pub fn title_order(&self) -> Cow<'_, [u32]> {
+        if self.options.lazy_tables {
+            Cow::Owned((0..self.article_count).map(|idx| self.title_entry(idx)).collect())
+        } else {
+            Cow::Borrowed(&self.article_list)
+        }
+    }
+
+    /// Iterates over the entries with URL indices in `start..end`, sorted by URL
+    ///
+    /// This is handy for splitting the archive into shards to process in parallel.  `end` is
+    /// clamped to `article_count`, and if `start` is past `end`, the iterator is empty.
+    pub fn iter_range(&self, start: u32, end: u32) -> DirectoryIterator<'_> {
+        let end = std::cmp::min(end, self.article_count);
+        DirectoryIterator {
+            max_articles: end,
+            article_to_yield: std::cmp::min(start, end),
+            zim: self
+        }
+    }
+
+    /// Iterates over the entries with the `LinkTarget` mimetype, sorted by URL
+    ///
+    /// A link target marks a url that pages link to but that has no content of its own, and
+    /// unlike a redirect, it doesn't point anywhere else either; its `target` is None.  A
+    /// redirect (`Target::Redirect`) stands in for another entry and can be followed with
+    /// `resolve_entry`.  Entries that can't be read are skipped.
+    pub fn link_targets(&self) -> LinkTargetIterator<'_> {
+        LinkTargetIterator { inner: DirectoryIterator::new(self) }
+    }
+
+    /// Iterates over the namespace, url, and mimetype of every entry, sorted by URL
+    ///
+    /// This is meant for building search indexes over very large archives.  The title is
+    /// deliberately left out: it isn't even parsed, which saves an allocation per entry over
+    /// `iterate_by_urls`.  Use `iterate_by_urls` when the full entry is needed.
+    pub fn index_entries(&self) -> IndexEntryIterator<'_> {
+        IndexEntryIterator { inner: DirectoryIterator::new(self) }
+    }
+
+    /// Iterates over articles sorted by URL, yielding each one's URL index along with it
+    ///
+    /// Since this goes in url order, the index is the same as the position in the iteration, and
+    /// it's the index `get_by_url_index` takes to get the same entry back.
+    pub fn iter_by_urls_enumerated(&self) -> EnumeratedDirectoryIterator<'_> {
+        EnumeratedDirectoryIterator { inner: DirectoryIterator::new(self) }
+    }
+
+    /// Iterates over articles sorted by URL, like `iterate_by_urls`, but without copying the urls
+    /// and titles
+    ///
+    /// The entries borrow their strings straight from the archive, so a full scan that only
+    /// compares urls or titles doesn't allocate for each entry.  The entry cache isn't used.
+    pub fn iterate_by_urls_ref(&self) -> DirectoryRefIterator<'_> {
+        DirectoryRefIterator { inner: DirectoryIterator::new(self) }
+    }
+
+    /// Returns the `DirectoryEntry` for the article found at the given URL index.
+    ///
+    /// idx must be between 0 and `article_count`
+    pub fn get_by_url_index(&self, idx: u32) -> Option<DirectoryEntry> {
+        if let Some(entry) = self.entry_cache.get(idx) {
+            return Some(entry);
+        }
+        let entry = match self.read_entry(idx) {
+            Ok(entry) => entry,
+            Err(_) => return None
+        };
+        self.entry_cache.insert(idx, &entry);
+        Some(entry)
+    }
+
+    /// Parses the directory entry at the given URL index
+    fn read_entry(&self, idx: u32) -> Result<DirectoryEntry, ParsingError> {
+        if idx >= self.article_count {
+            return Err(ParsingError::new(ParseErrorKind::OutOfRange, "No such entry").in_entry(idx));
+        }
+        let entry_offset = try!(to_usize(self.url_offset(idx)).map_err(|e| e.in_entry(idx)));
+        let slice = try!(self.region(entry_offset, usize::MAX).map_err(|e| e.in_entry(idx)));
+        DirectoryEntry::new(self, slice).map_err(|e| e.in_entry(idx).at_offset(entry_offset as u64))
+    }
+
+    /// Parses the directory entry at the given URL index, borrowing its strings
+    fn read_entry_ref(&self, idx: u32) -> Result<DirectoryEntryRef<'_>, ParsingError> {
+        if idx >= self.article_count {
+            return Err(ParsingError::new(ParseErrorKind::OutOfRange, "No such entry").in_entry(idx));
+        }
+        let entry_offset = try!(to_usize(self.url_offset(idx)).map_err(|e| e.in_entry(idx)));
+        let slice = try!(self.region(entry_offset, usize::MAX).map_err(|e| e.in_entry(idx)));
+        DirectoryEntryRef::new(self, slice).map_err(|e| e.in_entry(idx).at_offset(entry_offset as u64))
+    }
+
+    /// Reads just the namespace, url, and mimetype of the entry at the given URL index
+    fn read_index_entry(&self, idx: u32) -> Result<(char, String, MimeType), ParsingError> {
+        let (mime_id, namespace, url) = try!(self.read_entry_url(idx));
+        let mime_type = self.get_mimetype(mime_id).unwrap_or(MimeType::Unknown(mime_id));
+        Ok((namespace, url.to_owned(), mime_type))
+    }
+
+    /// Reads the mimetype id, namespace, and url of the entry at the given URL index, stopping
+    /// before the title
+    fn read_entry_url(&self, idx: u32) -> Result<(u16, char, &str), ParsingError> {
+        if idx >= self.article_count {
+            return Err(ParsingError::new(ParseErrorKind::OutOfRange, "No such entry").in_entry(idx));
+        }
+        let entry_offset = try!(to_usize(self.url_offset(idx)).map_err(|e| e.in_entry(idx)));
+        let s = try!(self.region(entry_offset, usize::MAX).map_err(|e| e.in_entry(idx)));
+        let parse = || {
+            let mime_id = try!(Cursor::new(s).read_u16::<LittleEndian>());
+            let header_len = match mime_id {
+                0xffff => 12,
+                0xfffe | 0xfffd => 8,
+                _ => 16
+            };
+            let url = try!(c_str_at(s, header_len));
+            Ok((mime_id, s[3] as char, url))
+        };
+        parse().map_err(|e: ParsingError| e.in_entry(idx).at_offset(entry_offset as u64))
+    }
+
+    /// Returns the raw bytes of the directory entry at the given URL index
+    ///
+    /// This is an escape hatch for reading fields this crate doesn't parse.  The slice starts at
+    /// the entry's offset in the url table.  Entries aren't necessarily stored in url order, so
+    /// the end isn't taken from the next offset in the table; instead, the entry's header is read
+    /// to find where its strings start, and the slice ends after the title's terminating NUL.
+    /// Returns None if `idx` is out of range or the entry can't be parsed.  To go over every
+    /// entry, use `(0..zim.article_count).filter_map(|idx| zim.raw_entry(idx))`.
+    pub fn raw_entry(&self, idx: u32) -> Option<&[u8]> {
+        self.read_entry_ref(idx).ok().map(|entry| entry.data)
+    }
+
+    /// Returns the metadata of the entry at the given URL index, without resolving its mimetype
+    ///
+    /// This does less work than `get_by_url_index`: the target isn't decoded, the mimetype isn't
+    /// looked up, and the entry cache isn't used, which adds up when cataloging millions of
+    /// entries.  Returns None if `idx` is out of range or the entry can't be parsed.
+    pub fn entry_info(&self, idx: u32) -> Option<EntryInfo> {
+        self.read_entry_ref(idx).ok().map(|entry| {
+            let mime_id = entry.mime_id();
+            EntryInfo {
+                namespace: entry.namespace(),
+                url: entry.url().to_owned(),
+                title: entry.title().to_owned(),
+                mime_id: mime_id,
+                is_redirect: mime_id == 0xffff,
+            }
+        })
+    }
+
+    /// Finds the `DirectoryEntry` for the given namespace and url
+    ///
+    /// The url should not be percent-encoded; use `decode_url` on links taken from article HTML.
+    /// This does a binary search over the URL table, so it costs O(log n) entry reads per lookup.
+    /// If you're going to do a lot of lookups, see `build_url_index`.
+    pub fn get_by_url(&self, namespace: char, url: &str) -> Option<DirectoryEntry> {
+        self.locate(namespace, url).ok().and_then(|idx| self.get_by_url_index(idx))
+    }
+
+    /// Binary searches the URL table for the given namespace and url
+    ///
+    /// Like `slice::binary_search`, this returns `Ok` with the URL index of the entry if it's
+    /// found, or `Err` with the index where it would be inserted to keep the table sorted if it
+    /// isn't.  The entry at that index (if any) is the next one after the missing url, which is
+    /// useful for suggesting a nearby article.  If an entry can't be read during the search, the
+    /// search stops and `Err` is returned with that entry's index.
+    pub fn locate(&self, namespace: char, url: &str) -> Result<u32, u32> {
+        let mut lo = 0;
+        let mut hi = self.article_count;
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            let entry = match self.get_by_url_index(mid) {
+                Some(entry) => entry,
+                None => return Err(mid)
+            };
+            match (entry.namespace, entry.url.as_str()).cmp(&(namespace, url)) {
+                std::cmp::Ordering::Less => lo = mid + 1,
+                std::cmp::Ordering::Greater => hi = mid,
+                std::cmp::Ordering::Equal => return Ok(mid)
+            }
+        }
+        Err(lo)
+    }
+
+    /// Returns the URL index of an entry read from this archive
+    ///
+    /// This is a binary search for the entry's (namespace, url), for when the entry came from
+    /// somewhere that didn't give its index, such as `iterate_by_urls`.  The index can be stored
+    /// and passed to `get_by_url_index` later to get the entry back.  Returns None if this
+    /// archive has no entry with that namespace and url.
+    pub fn index_of(&self, entry: &DirectoryEntry) -> Option<u32> {
+        self.locate(entry.namespace, &entry.url).ok()
+    }
+
+    /// Returns the mimetype of the article at the given namespace and url, or None if there's no
+    /// such article
+    ///
+    /// Redirects are followed, so this gives the mimetype of the entry that would actually be
+    /// served; a redirect that loops or points outside the archive gives None.  Only directory
+    /// entries are read, never any cluster data, so this is cheap enough to call before deciding
+    /// whether to read the article itself.
+    pub fn head(&self, namespace: char, url: &str) -> Option<MimeType> {
+        let entry = self.get_by_url(namespace, url)?;
+        self.resolve_entry(&entry).ok().map(|entry| entry.mime_type)
+    }
+
+    /// Finds the `DirectoryEntry` for a full path, such as `A/Foo` or `M/Counter`
+    ///
+    /// A leading `/` is ignored.  If the path starts with a single character followed by a `/`,
+    /// that character is the namespace and the rest is the url.  Otherwise (for example `Foo`, or
+    /// `Foo/Bar`), the whole path is taken as a url in the `A` namespace.  As with `get_by_url`,
+    /// the path should not be percent-encoded.
+    pub fn get_by_path(&self, path: &str) -> Option<DirectoryEntry> {
+        let path = path.trim_start_matches('/');
+        let mut chars = path.chars();
+        match (chars.next(), chars.next()) {
+            (Some(namespace), Some('/')) => self.get_by_url(namespace, chars.as_str()),
+            _ => self.get_by_url('A', path)
+        }
+    }
+
+    /// Finds the entry a link in the article `from` points at
+    ///
+    /// Links in article HTML are relative to the article's own path, `{namespace}/{url}`, so
+    /// from `A/Foo`, `Bar` is `A/Bar` and `../I/logo.png` is `I/logo.png`.  The link is resolved
+    /// like a relative url on the web:
+    ///
+    /// * anything after a `#` or `?` is dropped, and a link that's only a fragment (`#section`)
+    ///   points at `from` itself
+    /// * a link starting with `/` starts from the root of the archive rather than `from`'s
+    ///   directory
+    /// * `.` segments and empty segments are dropped, and `..` removes the segment before it;
+    ///   going above the root gives None
+    /// * each segment is percent-decoded
+    ///
+    /// The resulting path is then looked up with `get_by_path`.  Links with a scheme (such as
+    /// `https:`, `mailto:` or `javascript:`) and links starting with `//` (which keep the page's
+    /// scheme but go to another host) point outside the archive, so they give None, as do links
+    /// to entries that don't exist.
+    pub fn resolve_relative(&self, from: &DirectoryEntry, link: &str) -> Option<DirectoryEntry> {
+        if has_scheme(link) || link.starts_with("//") {
+            return None;
+        }
+        let link = link.split(['#', '?']).next().unwrap_or("");
+        if link.is_empty() {
+            return Some(from.clone());
+        }
+
+        let mut segments: Vec<String> = Vec::new();
+        if !link.starts_with('/') {
+            // the directory `from` is in
+            segments.push(from.namespace.to_string());
+            segments.extend(from.url.split('/').map(|s| s.to_owned()));
+            segments.pop();
+        }
+        for segment in link.split('/') {
+            match segment {
+                "" | "." => {}
+                ".." => {
+                    segments.pop()?;
+                }
+                segment => segments.push(decode_url(segment)),
+            }
+        }
+        self.get_by_path(&segments.join("/"))
+    }
+
+    /// Reads the whole URL table into a `UrlIndex` for use with `get_by_url_indexed`
+    ///
+    /// This reads every directory entry once, and the resulting index holds a copy of every url
+    /// in the archive, so it can take a lot of memory for large archives.  In exchange, lookups
+    /// are O(1) and don't need to read any entries other than the one being looked up.  For a
+    /// handful of lookups, `get_by_url` is the better choice.
+    pub fn build_url_index(&self) -> UrlIndex {
+        let mut map = HashMap::with_capacity(self.article_count as usize);
+        for idx in 0..self.article_count {
+            if let Some(entry) = self.get_by_url_index(idx) {
+                map.insert((entry.namespace, entry.url), idx);
+            }
+        }
+        UrlIndex { map: map }
+    }
+
+    /// Finds the `DirectoryEntry` for the given namespace and url, using a prebuilt `UrlIndex`
+    ///
+    /// The index must have been built from this same archive.  As with `get_by_url`, the url
+    /// should not be percent-encoded.
+    pub fn get_by_url_indexed(&self, index: &UrlIndex, namespace: char, url: &str) -> Option<DirectoryEntry> {
+        index.map.get(&(namespace, url.to_owned())).and_then(|&idx| self.get_by_url_index(idx))
+    }
+
+    /// Picks a random article from the `A` namespace, skipping redirects
+    ///
+    /// Up to 10 picks are made before giving up and returning None.  See
+    /// `random_article_with_retries` to change that limit.  Needs the `rand` feature.
+    #[cfg(feature = "rand")]
+    pub fn random_article<R: rand::Rng + ?Sized>(&self, rng: &mut R) -> Option<DirectoryEntry> {
+        self.random_article_with_retries(rng, 10)
+    }
+
+    /// Picks a random article from the `A` namespace, skipping redirects, making at most
+    /// `max_tries` picks
+    ///
+    /// Only entries with content (not redirects, link targets, or deleted entries) are returned.
+    /// Needs the `rand` feature.
+    #[cfg(feature = "rand")]
+    pub fn random_article_with_retries<R: rand::Rng + ?Sized>(&self, rng: &mut R, max_tries: u32) -> Option<DirectoryEntry> {
+        self.pick_random_article(|n| rng.gen_range(0..n), max_tries)
+    }
+
+    /// Picks a random article as `random_article_with_retries` does, with `random(n)` returning
+    /// a random number in `0..n`
+    #[cfg_attr(not(any(test, feature = "rand")), allow(dead_code))]
+    fn pick_random_article<F: FnMut(u32) -> u32>(&self, mut random: F, max_tries: u32) -> Option<DirectoryEntry> {
+        let (start, end) = self.namespace_range('A');
+        if start == end {
+            return None;
+        }
+        for _ in 0..max_tries {
+            let idx = start + random(end - start);
+            if let Some(entry) = self.get_by_url_index(idx) {
+                if let Some(Target::Cluster(..)) = entry.target {
+                    return Some(entry);
+                }
+            }
+        }
+        None
+    }
+
+    /// Iterates over every entry in the `M` (metadata) namespace, as (key, value) pairs
+    ///
+    /// The key is the entry's url (such as `Title` or `Illustration_48x48@1`), without the `M/`.
+    /// Each value is read as it's reached, following redirects; entries whose data can't be read
+    /// are skipped.  Only the `M` namespace is visited, found by binary search.
+    pub fn metadata_entries(&self) -> MetadataIterator<'_> {
+        let (start, end) = self.namespace_range('M');
+        MetadataIterator { zim: self, next: start, end: end }
+    }
+
+    /// Returns the languages of the archive's content, from the `M/Language` metadata entry
+    ///
+    /// These are ISO-639-3 codes such as `eng`.  Archives with content in several languages list
+    /// them all, separated by commas or semicolons.  Returns an empty Vec if the archive has no
+    /// language metadata.
+    pub fn languages(&self) -> Vec<String> {
+        match self.metadata_string("Language") {
+            Some(value) => value.split([',', ';'])
+                .map(|code| code.trim())
+                .filter(|code| !code.is_empty())
+                .map(|code| code.to_owned())
+                .collect(),
+            None => Vec::new()
+        }
+    }
+
+    /// Returns the archive's illustration (icon) of the given size, from `M/Illustration_{size}x{size}@1`
+    ///
+    /// Illustrations are square PNGs; 48x48 is the size every archive is meant to have.  Returns
+    /// None if there's no illustration of that size.  See `available_illustration_sizes`.
+    pub fn illustration_sized(&self, size: u32) -> Option<Vec<u8>> {
+        self.metadata_bytes(&format!("Illustration_{}x{}@1", size, size))
+    }
+
+    /// Returns the sizes of the illustrations in the archive, smallest first
+    ///
+    /// Each size can be passed to `illustration_sized`.  Only square illustrations at a scale of
+    /// 1 are listed.  Returns an empty Vec if the archive has no illustrations (older archives
+    /// have a `-/favicon` instead).
+    pub fn available_illustration_sizes(&self) -> Vec<u32> {
+        let (start, end) = self.namespace_range('M');
+        let mut sizes: Vec<u32> = (start..end)
+            .filter_map(|idx| self.read_entry_ref(idx).ok())
+            .filter_map(|entry| parse_illustration_size(entry.url()))
+            .collect();
+        sizes.sort();
+        sizes.dedup();
+        sizes
+    }
+
+    /// Reads the given metadata entry as a string, following redirects
+    fn metadata_string(&self, key: &str) -> Option<String> {
+        self.metadata_bytes(key).map(|data| String::from_utf8_lossy(&data).into_owned())
+    }
+
+    /// Reads the data of the given metadata entry, following redirects
+    fn metadata_bytes(&self, key: &str) -> Option<Vec<u8>> {
+        let entry = self.get_by_url('M', key)?;
+        let blob = self.resolve_entry(&entry).and_then(|target| self.read_blob(&target));
+        blob.ok().map(|blob| blob.data)
+    }
+
+    /// Returns the namespaces that have at least one entry in this archive, in order
+    ///
+    /// Since the url table is sorted by namespace, this only has to binary search for where each
+    /// namespace ends, rather than reading every entry.
+    pub fn namespaces(&self) -> Vec<char> {
+        let mut namespaces = Vec::new();
+        let mut idx = 0;
+        while idx < self.article_count {
+            let namespace = self.namespace_at(idx);
+            namespaces.push(namespace);
+            // if the table isn't actually sorted, the search could land before idx
+            idx = std::cmp::max(self.namespace_range(namespace).1, idx + 1);
+        }
+        namespaces
+    }
+
+    /// Returns true if the archive has at least one entry in the given namespace
+    ///
+    /// This is a single binary search, so it's cheaper than `namespaces` when you only care about
+    /// one namespace.
+    pub fn has_namespace(&self, namespace: char) -> bool {
+        let idx = self.namespace_bound(&|ns| ns >= namespace);
+        idx < self.article_count && self.namespace_at(idx) == namespace
+    }
+
+    /// Returns the number of entries in the given namespace
+    ///
+    /// For the `A` namespace, this is the number of articles most readers would show.  Like
+    /// `namespaces`, this binary searches the url table rather than reading every entry.  Note
+    /// that redirects are entries too, so they're included in the count.
+    pub fn article_count_in_namespace(&self, namespace: char) -> u32 {
+        let (start, end) = self.namespace_range(namespace);
+        end - start
+    }
+
+    /// Returns the entry with the lowest url in the given namespace
+    ///
+    /// This is found with a single binary search, without iterating over the namespace.  Returns
+    /// None if the namespace is empty.
+    pub fn first_in_namespace(&self, namespace: char) -> Option<DirectoryEntry> {
+        let idx = self.namespace_bound(&|ns| ns >= namespace);
+        if idx < self.article_count && self.namespace_at(idx) == namespace {
+            self.get_by_url_index(idx)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the entry with the highest url in the given namespace
+    ///
+    /// Like `first_in_namespace`, this is a single binary search.  Returns None if the namespace
+    /// is empty.
+    pub fn last_in_namespace(&self, namespace: char) -> Option<DirectoryEntry> {
+        let end = self.namespace_bound(&|ns| ns > namespace);
+        if end > 0 && self.namespace_at(end - 1) == namespace {
+            self.get_by_url_index(end - 1)
+        } else {
+            None
+        }
+    }
+
+    /// Iterates over the urls of the entries in the given namespace, in url order
+    ///
+    /// This is for things like sitemaps, which only need the urls: the namespace's bounds are
+    /// found by binary search, and each entry is only read as far as its url, skipping the title.
+    /// Redirects are included, since they're entries too.  Entries that can't be read are
+    /// skipped.
+    pub fn urls_in_namespace(&self, namespace: char) -> NamespaceUrlIterator<'_> {
+        let (start, end) = self.namespace_range(namespace);
+        NamespaceUrlIterator { zim: self, next: start, end: end }
+    }
+
+    /// Reads every entry in the given namespace, in url order
+    ///
+    /// The namespace's bounds are found by binary search, so only its own entries are read, and
+    /// the Vec is allocated at the right size up front.  Entries that can't be read are left out.
+    pub fn collect_namespace(&self, namespace: char) -> Vec<DirectoryEntry> {
+        let (start, end) = self.namespace_range(namespace);
+        let mut entries = Vec::with_capacity((end - start) as usize);
+        entries.extend((start..end).filter_map(|idx| self.read_entry(idx).ok()));
+        entries
+    }
+
+    /// Returns the range of URL indices of the entries in the given namespace, as (start, end)
+    fn namespace_range(&self, namespace: char) -> (u32, u32) {
+        (self.namespace_bound(&|ns| ns >= namespace), self.namespace_bound(&|ns| ns > namespace))
+    }
+
+    /// Returns the first URL index whose namespace matches `pred`, or `article_count` if none do
+    ///
+    /// The url table is sorted by namespace first, so this is a binary search; `pred` has to be
+    /// false for some prefix of the namespaces and true for the rest.
+    fn namespace_bound(&self, pred: &dyn Fn(char) -> bool) -> u32 {
+        let mut lo = 0;
+        let mut hi = self.article_count;
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            if pred(self.namespace_at(mid)) {
+                hi = mid;
+            } else {
+                lo = mid + 1;
+            }
+        }
+        lo
+    }
+
+    /// Reads just the namespace of the entry at the given URL index
+    fn namespace_at(&self, idx: u32) -> char {
+        let namespace = to_usize(self.url_offset(idx)).and_then(|off| self.region(off.saturating_add(3), 1));
+        namespace.ok().and_then(|ns| ns.first()).map_or('\0', |&ns| ns as char)
+    }
+
+    /// Returns the given `Cluster`
+    /// 
+    /// idx must be between 0 and `cluster_count`
+    pub fn get_cluster(&self, idx: u32) -> Option<Cluster> {
+        self.cached_cluster(idx).ok()
+    }
+
+    /// Decompresses the given cluster into `buf`, and returns a view of it
+    ///
+    /// This is a lower-level alternative to `get_cluster` for bulk extraction: passing the same
+    /// `buf` for each cluster reuses its allocation, instead of allocating a new buffer for every
+    /// cluster.  `buf` is cleared first.  Uncompressed clusters are read in place, as with
+    /// `cluster_view`, and `buf` is left empty.  The cluster cache isn't used.
+    pub fn decompress_cluster_into<'a>(&'a self, idx: u32, buf: &'a mut Vec<u8>) -> Result<ClusterRef<'a>, ParsingError> {
+        buf.clear();
+        if idx >= self.cluster_count {
+            return Err(ParsingError::new(ParseErrorKind::OutOfRange, "No such cluster"));
+        }
+        self.decompress_cluster_into_inner(idx, buf)
+            .map_err(|e| e.in_cluster(idx).at_offset(self.cluster_offset(idx)))
+    }
+
+    fn decompress_cluster_into_inner<'a>(&'a self, idx: u32, buf: &'a mut Vec<u8>) -> Result<ClusterRef<'a>, ParsingError> {
+        let (this_cluster_off, next_cluster_off) = self.cluster_range(idx);
+        let slice = try!(self.cluster_data(idx));
+        let offset_size = blob_offset_size(slice[0]);
+        let start = self.options.on_decompress.as_ref().map(|_| Instant::now());
+        match Compression::from_byte(slice[0]) {
+            Compression::Xz => try!(decompress_xz_into(&slice[1..], offset_size, buf)),
+            Compression::Bzip2 => {
+                try!(bzip2::decompress_into(&slice[1..], buf).map_err(|msg| ParsingError::new(ParseErrorKind::Decompress, msg)))
+            }
+            Compression::Zlib => {
+                try!(decompress_zlib_into(&slice[1..], buf))
+            }
+            Compression::None => {
+                let data = &slice[1..];
+                return Ok(ClusterRef {
+                    idx: idx,
+                    start_off: this_cluster_off,
+                    blob_list: try!(parse_blob_list(data, offset_size)),
+                    data: ClusterBytes::Borrowed(data),
+                });
+            }
+            compression => return Err(unsupported_compression(compression))
+        }
+        if let (Some(hook), Some(start)) = (self.options.on_decompress.as_ref(), start) {
+            (hook.0)(&DecompressInfo {
+                cluster_idx: idx,
+                compressed_len: next_cluster_off - this_cluster_off,
+                decompressed_len: buf.len(),
+                duration: start.elapsed(),
+            });
+        }
+        Ok(ClusterRef {
+            idx: idx,
+            start_off: this_cluster_off,
+            blob_list: try!(parse_blob_list(buf, offset_size)),
+            data: ClusterBytes::Borrowed(buf),
+        })
+    }
+
+    /// Returns the cluster holding the data of `entry`
+    ///
+    /// The cluster comes from the cache, if there is one.  Returns None for redirects, link
+    /// targets, and deleted entries, which don't have any data, or if the cluster can't be read.
+    pub fn cluster_for(&self, entry: &DirectoryEntry) -> Option<Cluster> {
+        match entry.target {
+            Some(Target::Cluster(cid, _)) => self.get_cluster(cid),
+            _ => None
+        }
+    }
+
+    /// Returns a view of the given cluster that reads uncompressed clusters in place
+    ///
+    /// This avoids copying the cluster data when the cluster isn't compressed (which is common
+    /// for clusters of images and other binary data); see `ClusterRef`.  Returns None if idx isn't
+    /// between 0 and `cluster_count`, or if the cluster can't be read.
+    pub fn cluster_view(&self, idx: u32) -> Option<ClusterRef<'_>> {
+        if idx >= self.cluster_count {
+            return None;
+        }
+        let slice = match self.cluster_data(idx) {
+            Ok(slice) => slice,
+            Err(_) => return None
+        };
+        if Compression::from_byte(slice[0]) == Compression::None {
+            let data = &slice[1..];
+            parse_blob_list(data, blob_offset_size(slice[0])).ok().map(|blob_list| ClusterRef {
+                idx: idx,
+                start_off: self.cluster_offset(idx),
+                blob_list: blob_list,
+                data: ClusterBytes::Borrowed(data),
+            })
+        } else {
+            self.get_cluster(idx).map(|cluster| ClusterRef {
+                idx: idx,
+                start_off: cluster.start_off,
+                blob_list: cluster.blob_list,
+                data: ClusterBytes::Owned(cluster.data),
+            })
+        }
+    }
+
+    /// Decompresses the given clusters into the cluster cache, so later reads from them are fast
+    ///
+    /// This does nothing unless the cluster cache is enabled (see
+    /// `ZimOptions::cluster_cache_size`).  The cache only holds so many clusters, so if more are
+    /// listed than fit, only the last ones that fit are read; the rest would only be evicted
+    /// again.  Clusters that can't be read are skipped; the error will come up again when
+    /// something reads them.
+    pub fn prefetch_clusters(&self, indices: &[u32]) {
+        let capacity = self.options.cluster_cache_size;
+        let mut wanted = Vec::new();
+        for &idx in indices.iter().rev() {
+            if wanted.len() == capacity {
+                break;
+            }
+            if !wanted.contains(&idx) {
+                wanted.push(idx);
+            }
+        }
+        // read them in the order given, so the most recently used ones are the last listed
+        for &idx in wanted.iter().rev() {
+            let _ = self.cached_cluster(idx);
+        }
+    }
+
+    /// Returns the given `Cluster`, going through the cluster cache if it's enabled
+    fn cached_cluster(&self, idx: u32) -> Result<Cluster, ParsingError> {
+        if idx >= self.cluster_count {
+            return Err(ParsingError::new(ParseErrorKind::OutOfRange, "No such cluster"));
+        }
+        if let Some(cluster) = self.cluster_cache.get(idx) {
+            return Ok(cluster);
+        }
+        let cluster = try!(Cluster::new(self, idx));
+        self.cluster_cache.insert(idx, &cluster);
+        Ok(cluster)
+    }
+
+    /// Reads the data for the given entry, along with its mimetype
+    ///
+    /// If the cluster cache is enabled, the entry's cluster is read through it; otherwise only
+    /// this one blob is decompressed, as with `read_blob_partial`.  Entries without data
+    /// (redirects, link targets, and deleted entries) give a `NoContent` error, so they can be
+    /// told apart from archives that can't be read.
+    pub fn read_blob(&self, entry: &DirectoryEntry) -> Result<Blob, ParsingError> {
+        let (cid, bid) = match entry.target {
+            Some(Target::Cluster(cid, bid)) => (cid, bid),
+            _ => return Err(ParsingError::new(ParseErrorKind::NoContent, "Entry has no data"))
+        };
+        let data = if self.options.cluster_cache_size > 0 {
+            let cluster = try!(self.cached_cluster(cid));
+            Vec::from(try!(cluster.get_blob_checked(bid)))
+        } else {
+            try!(self.read_blob_partial(cid, bid))
+        };
+        Ok(Blob {
+            mime: entry.mime_type.clone(),
+            data: data,
+        })
+    }
+
+    /// Returns the data of the given blob in the given cluster
+    ///
+    /// This is for callers that already have an entry's `Target::Cluster`, and saves looking the
+    /// entry up again.  The cluster is decompressed (or taken from the cluster cache), so reading
+    /// several blobs from one cluster is cheap with the cache enabled.  Returns None if either
+    /// index is out of range or the cluster can't be read.
+    pub fn read_blob_at(&self, cluster: u32, blob: u32) -> Option<Vec<u8>> {
+        let cluster = match self.cached_cluster(cluster) {
+            Ok(cluster) => cluster,
+            Err(_) => return None
+        };
+        cluster.get_blob_checked(blob).ok().map(Vec::from)
+    }
+
+    /// Returns the mimetype and data of the entry at the given URL index
+    ///
+    /// If `follow_redirects` is true, redirects are followed (stopping at cycles) and the data of
+    /// the entry they end at is returned.  Returns None if `idx` is out of range, or if the entry
+    /// (or the one it redirects to) has no data or can't be read; without `follow_redirects`,
+    /// that includes redirects.
+    pub fn read_by_url_index(&self, idx: u32, follow_redirects: bool) -> Option<(MimeType, Vec<u8>)> {
+        let mut entry = self.get_by_url_index(idx)?;
+        if follow_redirects {
+            entry = match self.resolve_entry(&entry) {
+                Ok(entry) => entry,
+                Err(_) => return None
+            };
+        }
+        self.read_blob(&entry).ok().map(|blob| (blob.mime, blob.data))
+    }
+
+    /// Returns the entry of a well-known resource, `W/{name}`
+    ///
+    /// Newer archives keep resources meant for readers and servers in the `W` namespace, such as
+    /// `W/mainPage`.  These are usually redirects; the entry is returned as is.
+    pub fn well_known(&self, name: &str) -> Option<DirectoryEntry> {
+        self.get_by_url('W', name)
+    }
+
+    /// Returns the main page's entry
+    ///
+    /// The main page index in the header takes precedence.  If the header doesn't set one (or it
+    /// can't be read), this falls back to the `W/mainPage` entry used by newer archives.  Either
+    /// way, the entry may be a redirect.
+    pub fn main_page(&self) -> Option<DirectoryEntry> {
+        self.main_page_idx.and_then(|idx| self.get_by_url_index(idx))
+            .or_else(|| self.well_known("mainPage"))
+    }
+
+    /// Returns the namespace and url of the main page set in the header
+    ///
+    /// Only the entry's namespace and url are parsed, and nothing is decompressed.  Returns None
+    /// if the header doesn't set a main page or its index is out of range; unlike `main_page`,
+    /// this doesn't fall back to `W/mainPage`.
+    pub fn main_page_url(&self) -> Option<(char, String)> {
+        self.main_page_idx
+            .and_then(|idx| self.read_entry_ref(idx).ok())
+            .map(|entry| (entry.namespace(), entry.url().to_owned()))
+    }
+
+    /// Returns the mimetype and data of the main page, following a redirect if it is one
+    ///
+    /// The main page is found as in `main_page`.  Returns None if the archive has no main page,
+    /// or if it (or the page it redirects to) can't be read.
+    pub fn main_page_content(&self) -> Option<(MimeType, Vec<u8>)> {
+        let entry = self.main_page()?;
+        let entry = match self.resolve_entry(&entry) {
+            Ok(entry) => entry,
+            Err(_) => return None
+        };
+        self.read_blob(&entry).ok().map(|blob| (blob.mime, blob.data))
+    }
+
+    /// Computes the SHA-256 digest of the given entry's data
+    ///
+    /// The entry's cluster is read through the cluster cache, so hashing several entries from the
+    /// same cluster only decompresses it once if the cache is enabled.  Returns None for entries
+    /// without data (redirects aren't followed) or whose data can't be read.  Two entries with
+    /// the same digest have the same content, which makes this useful for finding duplicates.
+    #[cfg(feature = "sha2")]
+    pub fn article_digest(&self, entry: &DirectoryEntry) -> Option<[u8; 32]> {
+        let (cid, bid) = match entry.target {
+            Some(Target::Cluster(cid, bid)) => (cid, bid),
+            _ => return None
+        };
+        let cluster = match self.cached_cluster(cid) {
+            Ok(cluster) => cluster,
+            Err(_) => return None
+        };
+        cluster.get_blob_checked(bid).ok().map(|data| {
+            let mut hasher = sha2::Sha256::new();
+            hasher.update(data);
+            hasher.finalize().into()
+        })
+    }
+
+    /// Computes the SHA-256 digest of every entry with data, keyed by url index
+    ///
+    /// Entries are processed a cluster at a time, so each cluster is decompressed once whether
+    /// or not the cluster cache is enabled.  Entries whose data can't be read are left out.
+    #[cfg(feature = "sha2")]
+    pub fn digest_all(&self) -> BTreeMap<u32, [u8; 32]> {
+        self.digest_range(self.iter_by_urls_enumerated())
+    }
+
+    /// Computes the SHA-256 digest of each of the given entries that has data, a cluster at a time
+    #[cfg(feature = "sha2")]
+    fn digest_range<I: Iterator<Item=(u32, DirectoryEntry)>>(&self, entries: I) -> BTreeMap<u32, [u8; 32]> {
+        let mut by_cluster: BTreeMap<u32, Vec<(u32, u32)>> = BTreeMap::new();
+        for (idx, entry) in entries {
+            if let Some(Target::Cluster(cid, bid)) = entry.target {
+                by_cluster.entry(cid).or_default().push((idx, bid));
+            }
+        }
+
+        let mut digests = BTreeMap::new();
+        for (cid, blobs) in by_cluster {
+            let cluster = match self.cached_cluster(cid) {
+                Ok(cluster) => cluster,
+                Err(_) => continue
+            };
+            for (idx, bid) in blobs {
+                if let Ok(data) = cluster.get_blob_checked(bid) {
+                    let mut hasher = sha2::Sha256::new();
+                    hasher.update(data);
+                    digests.insert(idx, hasher.finalize().into());
+                }
+            }
+        }
+        digests
+    }
+
+    /// Compares the articles (the `A` namespace) of this archive with those of `other`
+    ///
+    /// Articles are matched up by url and compared by the SHA-256 digest of their data, so
+    /// archives that hold the same articles are equivalent even if they're packed into clusters
+    /// differently or compressed differently.  Redirects are compared by the url they point to.
+    /// Titles, revisions, mimetypes and the other namespaces aren't compared.
+    ///
+    /// Every article's data is read, so this decompresses all the clusters of both archives that
+    /// hold articles (each one once).  Articles whose data can't be read count as changed.
+    #[cfg(feature = "sha2")]
+    pub fn diff(&self, other: &Zim) -> ArchiveDiff {
+        let ours = self.article_fingerprints();
+        let theirs = other.article_fingerprints();
+        let mut diff = ArchiveDiff::default();
+        for (url, fingerprint) in &ours {
+            match theirs.get(url) {
+                None => diff.removed.push(url.clone()),
+                Some(other) if *other != *fingerprint || *other == EntryFingerprint::Unreadable => {
+                    diff.changed.push(url.clone())
+                }
+                Some(_) => {}
+            }
+        }
+        diff.added.extend(theirs.keys().filter(|url| !ours.contains_key(*url)).cloned());
+        diff
+    }
+
+    /// Returns true if this archive and `other` have the same articles
+    ///
+    /// This is `diff(other).is_empty()`; see `diff` for what's compared.  Both archives are
+    /// decompressed in full.
+    #[cfg(feature = "sha2")]
+    pub fn content_equivalent(&self, other: &Zim) -> bool {
+        self.diff(other).is_empty()
+    }
+
+    /// Describes what each entry in the `A` namespace holds, keyed by url
+    #[cfg(feature = "sha2")]
+    fn article_fingerprints(&self) -> BTreeMap<String, EntryFingerprint> {
+        let (start, end) = self.namespace_range('A');
+        let entries: Vec<(u32, DirectoryEntry)> = (start..end)
+            .filter_map(|idx| self.read_entry(idx).ok().map(|entry| (idx, entry)))
+            .collect();
+        let mut digests = self.digest_range(entries.iter().cloned());
+        let mut fingerprints = BTreeMap::new();
+        for (idx, entry) in entries {
+            let fingerprint = match entry.target {
+                Some(Target::Cluster(..)) => {
+                    digests.remove(&idx).map_or(EntryFingerprint::Unreadable, EntryFingerprint::Data)
+                }
+                Some(Target::Redirect(target)) => match self.get_by_url_index(target) {
+                    Some(target) => EntryFingerprint::Redirect(target.namespace, target.url),
+                    None => EntryFingerprint::Unreadable
+                },
+                None => EntryFingerprint::NoData
+            };
+            fingerprints.insert(entry.url, fingerprint);
+        }
+        fingerprints
+    }
+
+    /// Returns the entries whose data is in the given cluster, in url order
+    ///
+    /// This has to read every entry in the archive, since entries are sorted by url rather than
+    /// by cluster.  To look up the entries of several clusters, use `cluster_entry_map` instead.
+    pub fn entries_in_cluster(&self, cluster_idx: u32) -> Vec<DirectoryEntry> {
+        self.iterate_by_urls().filter(|entry| match entry.target {
+            Some(Target::Cluster(cid, _)) => cid == cluster_idx,
+            _ => false
+        }).collect()
+    }
+
+    /// Returns the raw bytes of the given cluster, exactly as stored in the archive
+    ///
+    /// This starts with the compression type byte, followed by the (possibly compressed) cluster
+    /// data, and is read straight from the memory map without decompressing anything.  Returns
+    /// None if idx isn't between 0 and `cluster_count`.
+    pub fn raw_cluster_bytes(&self, idx: u32) -> Option<&[u8]> {
+        if idx >= self.cluster_count {
+            return None;
+        }
+        self.cluster_data(idx).ok()
+    }
+
+    /// Returns every kind of compression used by the clusters in this archive
+    ///
+    /// This only reads the first byte of each cluster, so it's fast even for large archives.
+    pub fn compression_types(&self) -> HashSet<Compression> {
+        (0..self.cluster_count).filter_map(|idx| self.cluster_compression(idx)).collect()
+    }
+
+    /// Returns true if any cluster in this archive uses the given compression
+    ///
+    /// Like `compression_types`, this only reads the first byte of each cluster.
+    pub fn uses_compression(&self, compression: Compression) -> bool {
+        (0..self.cluster_count).any(|idx| self.cluster_compression(idx) == Some(compression))
+    }
+
+    /// Reads the compression type of the given cluster, without reading the rest of it
+    fn cluster_compression(&self, idx: u32) -> Option<Compression> {
+        let byte = to_usize(self.cluster_offset(idx)).and_then(|off| self.region(off, 1));
+        byte.ok().and_then(|b| b.first()).map(|&b| Compression::from_byte(b))
+    }
+
+    /// Reads a single blob without decompressing the rest of its cluster
+    ///
+    /// For XZ clusters, the decoder is only run as far as the end of the requested blob, and the
+    /// output before the start of the blob is thrown away as it is decoded.  Uncompressed clusters
+    /// are read straight out of the memory map.  Any other compression type falls back to
+    /// decompressing the whole cluster with `get_cluster`.
+    ///
+    /// This is cheaper than `get_cluster` when you only want one article, but if you want several
+    /// blobs from the same cluster, `get_cluster` is the better choice.
+    pub fn read_blob_partial(&self, cluster_idx: u32, blob_idx: u32) -> Result<Vec<u8>, ParsingError> {
+        if cluster_idx >= self.cluster_count {
+            return Err(ParsingError::new(ParseErrorKind::OutOfRange, "No such cluster"));
+        }
+        self.read_blob_partial_inner(cluster_idx, blob_idx)
+            .map_err(|e| e.in_cluster(cluster_idx).at_offset(self.cluster_offset(cluster_idx)))
+    }
+
+    fn read_blob_partial_inner(&self, cluster_idx: u32, blob_idx: u32) -> Result<Vec<u8>, ParsingError> {
+        let slice = try!(self.cluster_data(cluster_idx));
+        let offset_size = blob_offset_size(slice[0]);
+        match Compression::from_byte(slice[0]) {
+            Compression::Xz => read_blob_from(try!(XzReader::new(&slice[1..])), blob_idx, offset_size),
+            Compression::None => read_blob_from(Cursor::new(&slice[1..]), blob_idx, offset_size),
+            _ => {
+                let cluster = try!(Cluster::new(self, cluster_idx));
+                Ok(Vec::from(try!(cluster.get_blob_checked(blob_idx))))
+            }
+        }
+    }
+
+    /// Writes the data for the given entry into `out`, returning the number of bytes written
+    ///
+    /// The entry's cluster is read through the cluster cache, if it's enabled, and the data is
+    /// written straight from the decompressed cluster without another copy.  If
+    /// `follow_redirects` is true, redirects are followed to the entry they point at; otherwise,
+    /// redirects (like link targets, deleted entries, and other entries without data) give an
+    /// `InvalidInput` error wrapping a `NoContent` `ParsingError`.
+    pub fn write_article<W: Write>(&self, entry: &DirectoryEntry, follow_redirects: bool, out: &mut W) -> io::Result<u64> {
+        let target = if follow_redirects {
+            try!(self.resolve_entry(entry)).target
+        } else {
+            entry.target.clone()
+        };
+        let (cid, bid) = match target {
+            Some(Target::Cluster(cid, bid)) => (cid, bid),
+            _ => return Err(From::from(ParsingError::new(ParseErrorKind::NoContent, "Entry has no data")))
+        };
+        let cluster = try!(self.cached_cluster(cid));
+        let data = try!(cluster.get_blob_checked(bid));
+        try!(out.write_all(data));
+        Ok(data.len() as u64)
+    }
+
+    /// Follows redirects starting at `entry` until reaching an entry that isn't a redirect
+    ///
+    /// If `entry` isn't a redirect, a copy of it is returned.  If the redirects loop back on
+    /// themselves, a `RedirectCycle` error is returned, with the url index of the first entry
+    /// that was visited twice.
+    pub fn resolve_entry(&self, entry: &DirectoryEntry) -> Result<DirectoryEntry, ParsingError> {
+        let mut visited = HashSet::new();
+        let mut entry = entry.clone();
+        while let Some(Target::Redirect(idx)) = entry.target {
+            if !visited.insert(idx) {
+                return Err(ParsingError::new(ParseErrorKind::RedirectCycle, "Redirect cycle").in_entry(idx));
+            }
+            entry = try!(self.read_entry(idx));
+        }
+        Ok(entry)
+    }
+
+    /// Returns the combined decompressed size of every cluster in the archive
+    ///
+    /// This is expensive: every compressed cluster has to be decompressed to find out how big it
+    /// is.  Decompressed data is counted and then thrown away, so memory use stays low (bzip2 and zlib
+    /// clusters are decompressed whole, but only one at a time), but expect this to take about as
+    /// long as reading the whole archive.
+    pub fn total_decompressed_size(&self) -> Result<u64, ParsingError> {
+        let mut total = 0;
+        for idx in 0..self.cluster_count {
+            total += try!(self.decompressed_size(idx));
+        }
+        Ok(total)
+    }
+
+    /// Returns the decompressed size of the given cluster, without keeping its data around
+    fn decompressed_size(&self, idx: u32) -> Result<u64, ParsingError> {
+        let slice = try!(self.cluster_data(idx));
+        Ok(match Compression::from_byte(slice[0]) {
+            Compression::Xz => try!(io::copy(&mut try!(XzReader::new(&slice[1..])), &mut io::sink())),
+            Compression::None => slice.len() as u64 - 1,
+            _ => try!(Cluster::new(self, idx)).decompressed_len() as u64
+        })
+    }
+
+    /// Returns the overall compression ratio of the archive's clusters, looking at every `step`th
+    /// cluster
+    ///
+    /// This is the combined decompressed size of the clusters looked at, divided by their
+    /// combined compressed size, so bigger clusters count for more; uncompressed clusters count
+    /// as a ratio of 1.0.  Each compressed cluster looked at has to be decompressed (and then
+    /// thrown away), so with a `step` of 1 this costs about as much as `total_decompressed_size`;
+    /// a `step` of 100 gives a decent estimate for a hundredth of the cost.  A `step` of 0 is
+    /// treated as 1.  Returns 1.0 for archives without clusters.
+    pub fn average_compression_ratio(&self, step: u32) -> Result<f64, ParsingError> {
+        let (mut compressed, mut decompressed) = (0, 0);
+        for idx in (0..self.cluster_count).step_by(std::cmp::max(step, 1) as usize) {
+            let len = try!(self.cluster_data(idx)).len() as u64;
+            compressed += len;
+            decompressed += match self.cluster_compression(idx) {
+                Some(Compression::None) => len,
+                _ => try!(self.decompressed_size(idx))
+            };
+        }
+        if compressed == 0 {
+            return Ok(1.0);
+        }
+        Ok(decompressed as f64 / compressed as f64)
+    }
+
+    /// Calls `f` with every article in this archive, along with its data, in cluster order
+    ///
+    /// This is the fastest way to process every article.  The entries are first grouped by
+    /// cluster, then the clusters are decompressed in order, each exactly once, and `f` is called
+    /// for each of their articles, in url order within a cluster.  Only one decompressed cluster
+    /// is held at a time (the cluster cache isn't used), though the entries of every article are
+    /// read up front.  Redirects and other entries without data are skipped, and an error is
+    /// returned if a cluster can't be read.  See `for_each_article_with_progress` to track
+    /// progress.
+    pub fn for_each_article_in_cluster_order<F>(&self, f: F) -> Result<(), ParsingError>
+        where F: FnMut(&DirectoryEntry, &[u8]) {
+        self.for_each_article_with_progress(f, |_, _| {})
+    }
+
+    /// Calls `f` with every article in this archive, along with its data
+    ///
+    /// Articles are visited in cluster order rather than url order, so that each cluster only has
+    /// to be decompressed once.  Redirects and other entries without data are skipped.
+    ///
+    /// `progress` is called once per cluster, after that cluster's articles have been visited,
+    /// with the number of clusters done so far and the total number of clusters.
+    pub fn for_each_article_with_progress<F, P>(&self, mut f: F, progress: P) -> Result<(), ParsingError>
+        where F: FnMut(&DirectoryEntry, &[u8]), P: FnMut(u32, u32) {
+        let cluster_map = self.cluster_entry_map();
+        self.for_each_in_cluster_map(cluster_map, |entry, data| { f(entry, data); Ok(()) }, progress)
+    }
+
+    /// Iterates over every article in this archive, along with its data
+    ///
+    /// Unlike `iterate_by_urls`, articles are returned in cluster order, so that each cluster only
+    /// has to be decompressed once; within a cluster, they are in url order.  Redirects and other
+    /// entries without data are skipped.  Clusters are read through the cluster cache, if it's
+    /// enabled.
+    ///
+    /// The entries of every article are read up front, before the first one is returned.  An
+    /// article whose data can't be found in its cluster gives an error, and iteration carries on
+    /// with the next article; if a whole cluster can't be read, its error is the last item.
+    ///
+    /// The archive is advised for sequential access (see `advise_sequential`) until the iterator
+    /// is dropped, when the advice from before is put back.
+    pub fn iter_article_content(&self) -> ArticleContentIterator<'_> {
+        let advice = SequentialAdvice::new(self);
+        let clusters: Vec<(u32, Vec<DirectoryEntry>)> = self.cluster_entry_map().into_iter()
+            .enumerate()
+            .filter(|(_, entries)| !entries.is_empty())
+            .map(|(cid, entries)| (cid as u32, entries))
+            .collect();
+        ArticleContentIterator {
+            zim: self,
+            clusters: clusters.into_iter(),
+            current: None,
+            _advice: advice,
+        }
+    }
+
+    /// Iterates over the entries with data in url order, grouping consecutive entries that are
+    /// in the same cluster
+    ///
+    /// Each item is a cluster index, along with the (blob index, entry) pairs of a run of
+    /// consecutive entries in that cluster.  Redirects and other entries without data are skipped
+    /// and don't break up a run.  Only one entry is read ahead, so unlike `iter_article_content`,
+    /// memory use doesn't grow with the size of the archive.
+    ///
+    /// This is a heuristic: in archives where url order follows cluster order, reading each
+    /// group's cluster once decompresses each cluster only once.  But if a cluster's entries are
+    /// scattered across the url table, that cluster shows up in several groups, so use the
+    /// cluster cache or `iter_article_content` for those.
+    pub fn iter_grouped_by_cluster(&self) -> ClusterGroupIterator<'_> {
+        ClusterGroupIterator { entries: DirectoryIterator::new(self), pending: None }
+    }
+
+    /// Tells the OS that the archive will be read mostly front to back
+    ///
+    /// This lets the kernel read ahead aggressively, which speeds up full extractions of
+    /// archives that aren't already in the page cache (on spinning disks, often by a lot).  This
+    /// is done automatically by `for_each_article_with_progress`, `extract_to` and
+    /// `iter_article_content`, which put back the advice from before (this, `advise_random`, or
+    /// the OS default) once they're done.  It does nothing on platforms without `madvise`.
+    pub fn advise_sequential(&self) {
+        self.advise(Advice::Sequential);
+    }
+
+    /// Tells the OS that the archive will be read in no particular order
+    ///
+    /// This turns off readahead, which avoids reading pages that won't be used when serving
+    /// scattered article requests.  It does nothing on platforms without `madvise`.
+    pub fn advise_random(&self) {
+        self.advise(Advice::Random);
+    }
+
+    #[cfg(unix)]
+    fn advise(&self, advice: Advice) {
+        self.advice.set(advice);
+        let advice = match advice {
+            Advice::Normal => libc::MADV_NORMAL,
+            Advice::Sequential => libc::MADV_SEQUENTIAL,
+            Advice::Random => libc::MADV_RANDOM,
+        };
+        // this is only a hint, so there's nothing useful to do if it fails
+        if let Storage::Mapped(ref view) = self.storage {
+            unsafe {
+                libc::madvise(view.ptr() as *mut libc::c_void, view.len(), advice);
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn advise(&self, advice: Advice) {
+        self.advice.set(advice);
+    }
+
+    /// Returns the total size of article data in each namespace, in bytes
+    ///
+    /// This is expensive, since every cluster has to be decompressed (once) to find out how big its
+    /// blobs are.  See `size_stats_with_progress` for a version that reports progress.
+    pub fn size_stats(&self) -> Result<BTreeMap<char, u64>, ParsingError> {
+        self.size_stats_with_progress(|_, _| {})
+    }
+
+    /// Returns the total size of article data in each namespace, reporting progress as in
+    /// `for_each_article_with_progress`
+    ///
+    /// If several entries point at the same blob, its size is counted once for each of them.
+    pub fn size_stats_with_progress<P: FnMut(u32, u32)>(&self, progress: P) -> Result<BTreeMap<char, u64>, ParsingError> {
+        let mut stats = BTreeMap::new();
+        try!(self.for_each_article_with_progress(|entry, data| {
+            *stats.entry(entry.namespace).or_insert(0) += data.len() as u64;
+        }, progress));
+        Ok(stats)
+    }
+
+    /// Extracts every article in this archive into `dir`
+    ///
+    /// See `extract_to_with_progress` for details.
+    pub fn extract_to<P: AsRef<Path>>(&self, dir: P) -> Result<(), ParsingError> {
+        self.extract_to_with_progress(dir, |_| true, |_, _| {})
+    }
+
+    /// Extracts the articles for which `filter` returns true into `dir`
+    ///
+    /// See `extract_to_with_progress` for details.
+    pub fn extract_to_filtered<P, F>(&self, dir: P, filter: F) -> Result<(), ParsingError>
+        where P: AsRef<Path>, F: Fn(&DirectoryEntry) -> bool {
+        self.extract_to_with_progress(dir, filter, |_, _| {})
+    }
+
+    /// Extracts the articles for which `filter` returns true into `dir`, reporting progress
+    ///
+    /// Each article is written to `dir/{namespace}/{url}`, creating directories as needed.
+    /// Entries are grouped by cluster, so that each cluster is only decompressed once, and only one
+    /// cluster is held in memory at a time.
+    ///
+    /// Redirects aren't written as files.  Instead, they are listed in `dir/redirects.txt`, one
+    /// per line, as `{namespace}/{url}` and the `{namespace}/{url}` of the redirect target,
+    /// separated by a tab.
+    ///
+    /// Entries with urls that would escape `dir` (such as ones containing `..`) are skipped.
+    ///
+    /// A url can't be both a file and a directory on disk, so an article whose path is also a
+    /// directory other articles go in (`A/Foo`, when there's an `A/Foo/Bar`) is written with a
+    /// `~1` suffix (`A/Foo~1`) instead, as is one whose path is the same as an earlier one's.
+    /// Each such article is listed in `dir/renamed.txt`, one per line, as `{namespace}/{url}`
+    /// and the path it was written to (relative to `dir`), separated by a tab.  The file is only
+    /// written if something was renamed.
+    ///
+    /// `progress` is called once per cluster, as in `for_each_article_with_progress`.
+    pub fn extract_to_with_progress<P, F, G>(&self, dir: P, filter: F, progress: G) -> Result<(), ParsingError>
+        where P: AsRef<Path>, F: Fn(&DirectoryEntry) -> bool, G: FnMut(u32, u32) {
+        let dir = dir.as_ref();
+        try!(std::fs::create_dir_all(dir));
+
+        let mut cluster_map = self.empty_cluster_map();
+        let mut files = Vec::new();
+        let mut redirects = io::BufWriter::new(try!(File::create(dir.join("redirects.txt"))));
+
+        for entry in self.iterate_by_urls() {
+            if !filter(&entry) {
+                continue;
+            }
+            match entry.target {
+                Some(Target::Cluster(cid, _)) if cid < self.cluster_count => {
+                    if let Some(path) = extract_path(dir, &entry) {
+                        files.push((entry.namespace, entry.url.clone(), path));
+                    }
+                    cluster_map[cid as usize].push(entry);
+                }
+                Some(Target::Redirect(redir)) => {
+                    if let Some(redir) = self.get_by_url_index(redir) {
+                        try!(writeln!(redirects, "{}/{}\t{}/{}", entry.namespace, entry.url,
+                                      redir.namespace, redir.url));
+                    }
+                }
+                _ => {}
+            }
+        }
+        try!(redirects.flush());
+
+        let renamed = renamed_extract_paths(&files);
+        if !renamed.is_empty() {
+            let mut manifest = io::BufWriter::new(try!(File::create(dir.join("renamed.txt"))));
+            for &(namespace, ref url, _) in &files {
+                if let Some(path) = renamed.get(&(namespace, url.clone())) {
+                    let relative = path.strip_prefix(dir).unwrap_or(path);
+                    try!(writeln!(manifest, "{}/{}\t{}", namespace, url, relative.display()));
+                }
+            }
+            try!(manifest.flush());
+        }
+
+        self.for_each_in_cluster_map(cluster_map, |entry, data| {
+            let out_path = match renamed.get(&(entry.namespace, entry.url.clone())) {
+                Some(path) => Some(path.clone()),
+                None => extract_path(dir, entry)
+            };
+            if let Some(out_path) = out_path {
+                if let Some(parent) = out_path.parent() {
+                    try!(std::fs::create_dir_all(parent));
+                }
+                let mut f = try!(File::create(&out_path));
+                try!(f.write_all(data));
+            }
+            Ok(())
+        }, progress)
+    }
+
+    /// Returns the entries of every article, grouped by the cluster their data is in
+    ///
+    /// The outer Vec has one element per cluster, holding the entries whose data is in that
+    /// cluster, in url order.  Building this reads every entry once; if you want the entries of
+    /// more than one or two clusters, this is cheaper than calling `entries_in_cluster` for each.
+    pub fn cluster_entry_map(&self) -> Vec<Vec<DirectoryEntry>> {
+        let mut cluster_map = self.empty_cluster_map();
+        for entry in self.iterate_by_urls() {
+            if let Some(Target::Cluster(cid, _)) = entry.target {
+                if cid < self.cluster_count {
+                    cluster_map[cid as usize].push(entry);
+                }
+            }
+        }
+        cluster_map
+    }
+
+    /// Returns one empty list of entries per cluster
+    fn empty_cluster_map(&self) -> Vec<Vec<DirectoryEntry>> {
+        (0..self.cluster_count).map(|_| Vec::new()).collect()
+    }
+
+    /// Decompresses each cluster in turn and calls `f` for each of the entries listed for it
+    fn for_each_in_cluster_map<F, P>(&self, cluster_map: Vec<Vec<DirectoryEntry>>, mut f: F, mut progress: P) -> Result<(), ParsingError>
+        where F: FnMut(&DirectoryEntry, &[u8]) -> Result<(), ParsingError>, P: FnMut(u32, u32) {
+        let _advice = SequentialAdvice::new(self);
+        let total = cluster_map.len() as u32;
+        for (cid, entries) in cluster_map.into_iter().enumerate() {
+            if !entries.is_empty() {
+                let cluster = try!(Cluster::new(self, cid as u32));
+                for entry in &entries {
+                    if let Some(Target::Cluster(_, bid)) = entry.target {
+                        try!(f(entry, try!(cluster.get_blob_checked(bid))));
+                    }
+                }
+            }
+            progress(cid as u32 + 1, total);
+        }
+        Ok(())
+    }
+
+    /// Checks that the offset tables, and the offsets in them, all point inside the file
+    fn check_ranges(&self) -> Result<(), ParsingError> {
+        let file_len = self.file_len();
+        let err = |msg| ParsingError::new(ParseErrorKind::OutOfRange, msg);
+
+        if self.has_checksum() && self.checksum_off.checked_add(16).is_none_or(|end| end > file_len) {
+            return Err(err("Checksum offset is out of range").at_offset(72));
+        }
+        let data_end = self.data_end();
+        let tables = [(self.url_tbl_off, self.article_count as u64 * 8),
+                      (self.title_tbl_off, self.article_count as u64 * 4),
+                      (self.cluster_tbl_off, self.cluster_count as u64 * 8)];
+        for &(off, len) in &tables {
+            if off.checked_add(len).is_none_or(|end| end > data_end) {
+                return Err(err("Offset table is out of range").at_offset(off));
+            }
+        }
+        for idx in 0..self.article_count {
+            if self.url_offset(idx) >= data_end {
+                return Err(err("Directory entry offset is out of range").in_entry(idx)
+                           .at_offset(self.url_tbl_off + idx as u64 * 8));
+            }
+        }
+        for idx in 0..self.article_count {
+            if self.title_entry(idx) >= self.article_count {
+                return Err(err("Title table entry is out of range")
+                           .at_offset(self.title_tbl_off + idx as u64 * 4));
+            }
+        }
+        let mut prev_off = None;
+        for idx in 0..self.cluster_count {
+            let off = self.cluster_offset(idx);
+            if prev_off.is_some_and(|prev| off <= prev) || off >= data_end {
+                return Err(err("Cluster offset is out of range").in_cluster(idx)
+                           .at_offset(self.cluster_tbl_off + idx as u64 * 8));
+            }
+            prev_off = Some(off);
+        }
+        Ok(())
+    }
+
+    /// Checks that the first byte of every cluster is a compression type this crate can decompress
+    fn check_cluster_types(&self) -> Result<(), ParsingError> {
+        for idx in 0..self.cluster_count {
+            let off = self.cluster_offset(idx);
+            let slice = try!(to_usize(off).and_then(|pos| self.region(pos, 1)).map_err(|e| e.in_cluster(idx).at_offset(off)));
+            let byte = match slice.first() {
+                Some(&byte) => byte,
+                None => return Err(ParsingError::new(ParseErrorKind::Truncated, "Cluster is past the end of the file")
+                                   .in_cluster(idx).at_offset(off)),
+            };
+            match Compression::from_byte(byte) {
+                Compression::Unknown(b) => return Err(ParsingError {
+                    cause: Some(From::from(format!("compression type {} isn't known", b))),
+                    ..ParsingError::new(ParseErrorKind::Decompress, "Unknown cluster compression type")
+                        .in_cluster(idx).at_offset(off)
+                }),
+                Compression::Zstd => return Err(unsupported_compression(Compression::Zstd).in_cluster(idx).at_offset(off)),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the url table is sorted by (namespace, url), and the title table by
+    /// (namespace, title)
+    ///
+    /// Lookups like `get_by_url` binary search these tables, so if they're out of order, entries
+    /// that are in the archive can fail to be found.  This reads every entry, so it's slow for
+    /// large archives.  The error's `url_index` is the first entry found out of order; for the
+    /// title table, its `offset` is the position of that entry in the title table.
+    pub fn verify_sorted(&self) -> Result<(), ParsingError> {
+        let mut prev: Option<DirectoryEntry> = None;
+        for idx in 0..self.article_count {
+            let entry = try!(self.read_entry(idx));
+            if prev.is_some_and(|prev| prev.sort_key() > entry.sort_key()) {
+                return Err(ParsingError::new(ParseErrorKind::Unsorted, "URL table is out of order")
+                           .in_entry(idx).at_offset(self.url_tbl_off + idx as u64 * 8));
+            }
+            prev = Some(entry);
+        }
+
+        // an empty title means the url is the title, and it's sorted that way
+        fn title_key(entry: &DirectoryEntry) -> (char, &str) {
+            (entry.namespace, if entry.title.is_empty() { &entry.url } else { &entry.title })
+        }
+        let mut prev: Option<DirectoryEntry> = None;
+        for pos in 0..self.article_count {
+            let idx = self.title_entry(pos);
+            let entry = try!(self.read_entry(idx));
+            if prev.is_some_and(|prev| title_key(&prev) > title_key(&entry)) {
+                return Err(ParsingError::new(ParseErrorKind::Unsorted, "Title table is out of order")
+                           .in_entry(idx).at_offset(self.title_tbl_off + pos as u64 * 4));
+            }
+            prev = Some(entry);
+        }
+        Ok(())
+    }
+
+    /// Checks that every directory entry can be parsed, and points at something that exists
+    fn check_entries(&self) -> Result<(), ParsingError> {
+        for idx in 0..self.article_count {
+            let entry = try!(self.read_entry(idx));
+            let in_range = match entry.target {
+                Some(Target::Redirect(target)) => target < self.article_count,
+                Some(Target::Cluster(cluster, _)) => cluster < self.cluster_count,
+                None => true
+            };
+            if !in_range {
+                return Err(ParsingError::new(ParseErrorKind::OutOfRange, "Entry target is out of range")
+                           .in_entry(idx).at_offset(self.url_offset(idx)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the blob offset tables of all the uncompressed clusters
+    fn check_clusters(&self) -> Result<(), ParsingError> {
+        for idx in 0..self.cluster_count {
+            let slice = try!(self.cluster_data(idx).map_err(|e| e.in_cluster(idx)));
+            if Compression::from_byte(slice[0]) == Compression::None {
+                try!(parse_blob_list(&slice[1..], blob_offset_size(slice[0])).map_err(|e| e.in_cluster(idx).at_offset(self.cluster_offset(idx))));
+            }
+        }
+        Ok(())
+    }
+
+    /// Computes the MD5 checksum of the file and compares it against the one stored in the file
+    fn checksum_matches(&self) -> bool {
+        if !self.has_checksum() {
+            return false;
+        }
+        let mut checksum = [0; 16];
+        if self.storage.read_at(self.checksum_off, &mut checksum).unwrap_or(0) < 16 {
+            return false;
+        }
+        self.compute_checksum(CHECKSUM_CHUNK_SIZE) == Some(checksum)
+    }
+
+    /// Computes the MD5 checksum of everything before the stored checksum
+    ///
+    /// The file is read sequentially, `chunk_size` bytes at a time, so only one chunk has to be
+    /// in memory at once.  Returns None if the file ends before the checksum offset.
+    fn compute_checksum(&self, chunk_size: usize) -> Option<[u8; 16]> {
+        let mut hasher = md5::Md5::new();
+        let mut buf = vec![0; chunk_size];
+        let mut off = 0;
+        while off < self.checksum_off {
+            let want = std::cmp::min(chunk_size as u64, self.checksum_off - off) as usize;
+            let read = self.storage.read_at(off, &mut buf[..want]).unwrap_or(0);
+            if read < want {
+                return None;
+            }
+            hasher.update(&buf[..read]);
+            off += read as u64;
+        }
+        Some(hasher.finalize().into())
+    }
+
+    /// Returns the start and end file offsets of the given cluster
+    fn cluster_range(&self, idx: u32) -> (u64, u64) {
+        let this_cluster_off = self.cluster_offset(idx);
+        let next_cluster_off = if idx + 1 < self.cluster_count {
+            self.cluster_offset(idx + 1)
+        } else {
+            self.data_end()
+        };
+        (this_cluster_off, next_cluster_off)
+    }
+
+    /// Returns the offset of the directory entry at the given URL index
+    ///
+    /// With lazy tables, an index past the end of the file gives an offset past the end of the
+    /// file, so reading from it fails rather than panicking.  The same goes for `title_entry` and
+    /// `cluster_offset`.
+    fn url_offset(&self, idx: u32) -> u64 {
+        if self.options.lazy_tables {
+            let data = to_usize(self.url_tbl_off.saturating_add(idx as u64 * 8)).and_then(|pos| self.region(pos, 8));
+            let mut cur = Cursor::new(data.unwrap_or(&[]));
+            cur.read_u64::<LittleEndian>().unwrap_or(u64::MAX)
+        } else {
+            self.url_list[idx as usize]
+        }
+    }
+
+    /// Returns the URL index at the given position in the title table
+    fn title_entry(&self, idx: u32) -> u32 {
+        if self.options.lazy_tables {
+            let data = to_usize(self.title_tbl_off.saturating_add(idx as u64 * 4)).and_then(|pos| self.region(pos, 4));
+            let mut cur = Cursor::new(data.unwrap_or(&[]));
+            cur.read_u32::<LittleEndian>().unwrap_or(u32::MAX)
+        } else {
+            self.article_list[idx as usize]
+        }
+    }
+
+    /// Returns the offset of the given cluster
+    fn cluster_offset(&self, idx: u32) -> u64 {
+        if self.options.lazy_tables {
+            let data = to_usize(self.cluster_tbl_off.saturating_add(idx as u64 * 8)).and_then(|pos| self.region(pos, 8));
+            let mut cur = Cursor::new(data.unwrap_or(&[]));
+            cur.read_u64::<LittleEndian>().unwrap_or(u64::MAX)
+        } else {
+            self.cluster_list[idx as usize]
+        }
+    }
+
+    /// Returns the raw bytes of the given cluster (including the compression byte)
+    fn cluster_data(&self, idx: u32) -> Result<&[u8], ParsingError> {
+        let (this_cluster_off, next_cluster_off) = self.cluster_range(idx);
+        if next_cluster_off <= this_cluster_off {
+            return Err(ParsingError::new(ParseErrorKind::OutOfRange, "Cluster offsets are out of order"));
+        }
+        let slice = try!(self.region(try!(to_usize(this_cluster_off)), try!(to_usize(next_cluster_off - this_cluster_off))));
+        if slice.is_empty() {
+            return Err(ParsingError::new(ParseErrorKind::Truncated, "Cluster is past the end of the file"));
+        }
+        Ok(slice)
+    }
+
+    /// Returns up to `len` bytes of the file starting at `off`
+    ///
+    /// See `Storage::region`.
+    fn region(&self, off: usize, len: usize) -> Result<&[u8], ParsingError> {
+        self.storage.region(off, len)
+    }
+
+}
+
+
+
+#[test]
+fn test_zim() {
+
+    // we want to handle all URLs from the same cluster at the same time,
+    // so build a map between cluster
+    // build a mapping from 
+
+    //println!("{:?}", zim.get_by_url_index(59357));
+
+    //let cluster = zim.get_cluster(201);
+    //let data = cluster.get_blob(6);
+    //let s = std::str::from_utf8(data).unwrap();
+    //println!("Cluster: {:?}", cluster);
+    //println!("data: {}", s);
+
+
+}
+
+#[test]
+fn test_xz_reader() {
+    // "hello", compressed with xz
+    let data: Vec<u8> = vec!(
+        0xfd,0x37,0x7a,0x58,0x5a,0x00,0x00,0x04,0xe6,0xd6,0xb4,0x46,0x02,0x00,0x21,0x01,
+        0x16,0x00,0x00,0x00,0x74,0x2f,0xe5,0xa3,0x01,0x00,0x04,0x68,0x65,0x6c,0x6c,0x6f,
+        0x00,0x00,0x00,0x00,0xb1,0x37,0xb9,0xdb,0xe5,0xda,0x1e,0x9b,0x00,0x01,0x1d,0x05,
+        0xb8,0x2d,0x80,0xaf,0x1f,0xb6,0xf3,0x7d,0x01,0x00,0x00,0x00,0x00,0x04,0x59,0x5a
+    );
+    let mut reader = XzReader::new(&data).ok().unwrap();
+    let mut buf = [0; 2];
+    reader.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"he");
+    let mut rest = Vec::new();
+    reader.read_to_end(&mut rest).unwrap();
+    assert_eq!(rest, b"llo");
+}
+
+#[test]
+fn test_read_blob_from() {
+    // three blobs: "ab", "", "cde"
+    let data: Vec<u8> = vec!(16,0,0,0, 18,0,0,0, 18,0,0,0, 21,0,0,0, b'a', b'b', b'c', b'd', b'e');
+    assert_eq!(read_blob_from(Cursor::new(&data), 0, 4).ok().unwrap(), b"ab");
+    assert_eq!(read_blob_from(Cursor::new(&data), 1, 4).ok().unwrap(), b"");
+    assert_eq!(read_blob_from(Cursor::new(&data), 2, 4).ok().unwrap(), b"cde");
+    assert!(read_blob_from(Cursor::new(&data), 3, 4).is_err());
+}
+
+#[cfg(not(feature = "liblzma"))]
+#[test]
+fn test_read_cluster_from() {
+    let data: Vec<u8> = vec!(16,0,0,0, 18,0,0,0, 18,0,0,0, 21,0,0,0, b'a', b'b', b'c', b'd', b'e');
+    let cluster = read_cluster_from(Cursor::new(&data), 4).ok().unwrap();
+    assert_eq!(cluster, data);
+    assert_eq!(read_cluster_from(Cursor::new(&data[..20]), 4).err().unwrap().kind(), ParseErrorKind::Truncated);
+    assert!(read_cluster_from(Cursor::new(&[3u8, 0, 0, 0]), 4).is_err());
+}
+
+#[test]
+fn test_decode_url() {
+    assert_eq!(decode_url("Caf%C3%A9"), "Café");
+    assert_eq!(decode_url("a%2fb%2Fc"), "a/b/c");
+    assert_eq!(decode_url("plain"), "plain");
+    assert_eq!(decode_url("100%"), "100%");
+    assert_eq!(decode_url("%zz%4"), "%zz%4");
+    assert_eq!(decode_url("%FF"), "\u{FFFD}");
+}
+
+#[test]
+fn test_iterate_by_urls_reversed() {
+    let zim = Zim::new(test_zim::sample_zim("iterate_reversed")).ok().unwrap();
+    let forward: Vec<String> = zim.iterate_by_urls().map(|e| e.url).collect();
+    let mut backward: Vec<String> = zim.iterate_by_urls().rev().map(|e| e.url).collect();
+    assert_eq!(forward.len(), zim.article_count as usize);
+    backward.reverse();
+    assert_eq!(forward, backward);
+
+    // alternating ends should meet in the middle without repeating anything
+    let mut iter = zim.iterate_by_urls();
+    let mut front = Vec::new();
+    let mut back = Vec::new();
+    while let Some(e) = iter.next() {
+        front.push(e.url);
+        match iter.next_back() {
+            Some(e) => back.push(e.url),
+            None => break
+        }
+    }
+    back.reverse();
+    front.extend(back);
+    assert_eq!(front, forward);
+}
+
+#[test]
+fn test_random_article() {
+    let zim = Zim::new(test_zim::sample_zim("random_article")).ok().unwrap();
+    // A/Bar, A/Baz (a redirect), A/Café, A/Foo
+    for i in 0..4 {
+        let mut picks = vec![i, 0].into_iter();
+        let entry = zim.pick_random_article(|n| { assert_eq!(n, 4); picks.next().unwrap() }, 10).unwrap();
+        assert_eq!(entry.namespace, 'A');
+        assert!(entry.url != "Baz");
+    }
+    assert!(zim.pick_random_article(|_| 1, 5).is_none());
+}
+
+#[cfg(feature = "rand")]
+#[test]
+fn test_random_article_rng() {
+    use rand::SeedableRng;
+    let zim = Zim::new(test_zim::sample_zim("random_article_rng")).ok().unwrap();
+    let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+    let mut seen = HashSet::new();
+    for _ in 0..100 {
+        let entry = zim.random_article(&mut rng).unwrap();
+        assert_eq!(entry.namespace, 'A');
+        seen.insert(entry.url);
+    }
+    let expected: HashSet<String> = ["Bar", "Café", "Foo"].iter().map(|url| url.to_string()).collect();
+    assert_eq!(seen, expected);
+    assert!(zim.random_article_with_retries(&mut rng, 0).is_none());
+}
+
+#[test]
+fn test_read_blob() {
+    let zim = Zim::new(test_zim::sample_zim("read_blob")).ok().unwrap();
+    let entry = zim.get_by_url('I', "logo.png").unwrap();
+    let blob = zim.read_blob(&entry).ok().unwrap();
+    assert_eq!(blob.mime, MimeType::Type("image/png".to_owned()));
+    assert_eq!(blob.data, b"\x89PNG");
+
+    let redirect = zim.get_by_url('A', "Baz").unwrap();
+    assert_eq!(zim.read_blob(&redirect).err().unwrap().kind(), ParseErrorKind::NoContent);
+}
+
+/// Writes the sample archive with A/Foo's blob index pointing past the end of its cluster
+#[cfg(test)]
+fn bad_blob_zim(name: &str) -> PathBuf {
+    let path = test_zim::sample_zim(name);
+    let mut data = std::fs::read(&path).unwrap();
+    let off = {
+        let zim = Zim::new(&path).ok().unwrap();
+        zim.url_offset(zim.locate('A', "Foo").unwrap()) as usize
+    };
+    // the blob number follows the mimetype, namespace, revision, and cluster number
+    (&mut data[off + 12..]).write_u32::<LittleEndian>(99).unwrap();
+    test_zim::write_file(&format!("{}_bad", name), &data)
+}
+
+#[test]
+fn test_bad_blob_index() {
+    let zim = Zim::new(bad_blob_zim("bad_blob_index")).ok().unwrap();
+    let items: Vec<Result<(DirectoryEntry, Vec<u8>), ParsingError>> = zim.iter_article_content().collect();
+    assert_eq!(items.len(), 6);
+    assert_eq!(items.iter().filter(|item| item.is_err()).count(), 1);
+    assert!(items.iter().any(|item| item.as_ref().ok().is_some_and(|(e, _)| e.url == "Bar")));
+
+    let err = zim.for_each_article_in_cluster_order(|_, _| {}).err().unwrap();
+    assert_eq!(err.kind(), ParseErrorKind::OutOfRange);
+    assert_eq!(err.cluster_index(), Some(0));
+    assert!(zim.size_stats().is_err());
+    let dir = std::env::temp_dir().join(format!("zim-test-{}-bad_blob_index", std::process::id()));
+    assert!(zim.extract_to(&dir).is_err());
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_extract_to() {
+    let zim = Zim::new(test_zim::sample_zim("extract_to")).ok().unwrap();
+    let dir = std::env::temp_dir().join(format!("zim-test-{}-extract_to", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    zim.extract_to(&dir).ok().unwrap();
+
+    for entry in test_zim::sample_entries() {
+        let path = dir.join(entry.namespace.to_string()).join(entry.url);
+        match entry.content {
+            test_zim::Content::Data(_, data) => assert_eq!(std::fs::read(&path).unwrap(), data),
+            _ => assert!(!path.exists())
+        }
+    }
+    let redirects = std::fs::read_to_string(dir.join("redirects.txt")).unwrap();
+    assert_eq!(redirects.lines().collect::<Vec<_>>(), vec!["A/Baz\tA/Foo"]);
+    assert!(!dir.join("renamed.txt").exists());
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_extract_to_collisions() {
+    use test_zim::{Content, TestEntry};
+    let entries = vec![
+        TestEntry { namespace: 'A', url: "Foo", title: "Foo", revision: 0, content: Content::Data("text/html", b"foo") },
+        TestEntry { namespace: 'A', url: "Foo/", title: "Foo/", revision: 0, content: Content::Data("text/html", b"foo dir") },
+        TestEntry { namespace: 'A', url: "Foo/Bar", title: "Foo/Bar", revision: 0, content: Content::Data("text/html", b"bar") },
+    ];
+    let zim = Zim::new(test_zim::write_zim("extract_to_collisions", entries, None)).ok().unwrap();
+    let dir = std::env::temp_dir().join(format!("zim-test-{}-extract_to_collisions", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    zim.extract_to(&dir).ok().unwrap();
+
+    assert_eq!(std::fs::read(dir.join("A/Foo/Bar")).unwrap(), b"bar");
+    assert_eq!(std::fs::read(dir.join("A/Foo~1")).unwrap(), b"foo");
+    assert_eq!(std::fs::read(dir.join("A/Foo~2")).unwrap(), b"foo dir");
+    let renamed = std::fs::read_to_string(dir.join("renamed.txt")).unwrap();
+    assert_eq!(renamed.lines().collect::<Vec<_>>(), vec!["A/Foo\tA/Foo~1", "A/Foo/\tA/Foo~2"]);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_size_stats() {
+    let zim = Zim::new(test_zim::sample_zim("size_stats")).ok().unwrap();
+    let stats = zim.size_stats().ok().unwrap();
+    assert_eq!(stats.get(&'A'), Some(&31));
+    assert_eq!(stats.get(&'I'), Some(&4));
+    assert_eq!(stats.get(&'M'), Some(&15));
+    assert_eq!(stats.len(), 3);
+}
+
+#[test]
+fn test_get_by_path() {
+    let zim = Zim::new(test_zim::sample_zim("get_by_path")).ok().unwrap();
+    assert_eq!(zim.get_by_path("A/Foo").unwrap().url, "Foo");
+    assert_eq!(zim.get_by_path("/A/Foo").unwrap().url, "Foo");
+    assert_eq!(zim.get_by_path("Foo").unwrap().namespace, 'A');
+    let counter = zim.get_by_path("M/Counter").unwrap();
+    assert_eq!((counter.namespace, counter.url.as_str()), ('M', "Counter"));
+    assert!(zim.get_by_path("Nope/Foo").is_none());
+    assert!(zim.get_by_path("A/").is_none());
+}
+
+#[test]
+fn test_iter_article_content() {
+    let zim = Zim::new(test_zim::sample_zim("iter_article_content")).ok().unwrap();
+    let articles: Vec<(String, Vec<u8>)> = zim.iter_article_content().map(|item| {
+        let (e, d) = item.ok().unwrap();
+        (e.url, d)
+    }).collect();
+    assert_eq!(articles.len(), 6);
+    assert!(articles.contains(&("Foo".to_owned(), b"<p>foo</p>".to_vec())));
+    assert!(!articles.iter().any(|(url, _)| url == "Baz"));
+}
+
+#[test]
+fn test_empty_zim() {
+    let zim = Zim::open_with(test_zim::write_zim("empty", Vec::new(), None),
+                             ZimOptions::new().validate_ranges(true).validate_checksum(true)).ok().unwrap();
+    assert_eq!(zim.len(), 0);
+    assert!(zim.is_empty());
+    assert_eq!(zim.cluster_count, 0);
+    assert_eq!(zim.mime_type_count(), 0);
+    assert!(zim.main_page_idx.is_none());
+    assert!(zim.iterate_by_urls().next().is_none());
+    assert!(zim.iterate_by_urls().next_back().is_none());
+    assert!(zim.iter_article_content().next().is_none());
+    assert!(zim.get_by_url_index(0).is_none());
+    assert!(zim.get_by_url('A', "Foo").is_none());
+    assert!(zim.get_cluster(0).is_none());
+    assert!(zim.read_blob_partial(0, 0).is_err());
+    assert!(zim.pick_random_article(|_| 0, 10).is_none());
+    assert_eq!(zim.total_decompressed_size().ok().unwrap(), 0);
+}
+
+#[test]
+fn test_mimetype_hash() {
+    let mut set = std::collections::HashSet::new();
+    set.insert(MimeType::Redirect);
+    set.insert(MimeType::LinkTarget);
+    set.insert(MimeType::DeletedEntry);
+    set.insert(MimeType::Type("text/html".to_owned()));
+    set.insert(MimeType::Type("text/html".to_owned()));
+    set.insert(MimeType::Type("image/png".to_owned()));
+    assert_eq!(set.len(), 5);
+    assert!(set.contains(&MimeType::Type("text/html".to_owned())));
+}
+
+#[test]
+fn test_write_article() {
+    let zim = Zim::new(test_zim::sample_zim("write_article")).ok().unwrap();
+    let mut out = Vec::new();
+    let entry = zim.get_by_url('A', "Foo").unwrap();
+    assert_eq!(zim.write_article(&entry, false, &mut out).unwrap(), 10);
+    assert_eq!(out, b"<p>foo</p>");
+
+    let redirect = zim.get_by_url('A', "Baz").unwrap();
+    assert!(zim.write_article(&redirect, false, &mut out).is_err());
+    out.clear();
+    zim.write_article(&redirect, true, &mut out).unwrap();
+    assert_eq!(out, b"<p>foo</p>");
+}
+
+#[test]
+fn test_get_blob_checked() {
+    let zim = Zim::new(test_zim::sample_zim("get_blob_checked")).ok().unwrap();
+    let cluster = zim.get_cluster(0).unwrap();
+    assert_eq!(cluster.get_blob_checked(0).ok().unwrap(), b"<p>bar</p>");
+    let err = cluster.get_blob_checked(6).err().unwrap();
+    assert_eq!(err.kind(), ParseErrorKind::OutOfRange);
+    assert_eq!(err.cluster_index(), Some(0));
+    assert!(err.to_string().contains("blob index 6 out of range, cluster has 6 blobs"));
+}
+
+#[test]
+fn test_redirect_cycle() {
+    use test_zim::{Content, TestEntry};
+    let entries = vec![
+        TestEntry { namespace: 'A', url: "X", title: "X", revision: 0, content: Content::Redirect('A', "Y") },
+        TestEntry { namespace: 'A', url: "Y", title: "Y", revision: 0, content: Content::Redirect('A', "X") },
+    ];
+    let zim = Zim::new(test_zim::write_zim("redirect_cycle", entries, None)).ok().unwrap();
+    let entry = zim.get_by_url('A', "X").unwrap();
+    let err = zim.resolve_entry(&entry).err().unwrap();
+    assert_eq!(err.kind(), ParseErrorKind::RedirectCycle);
+    assert_eq!(err.url_index(), Some(1));
+}
+
+#[test]
+fn test_encoded_len() {
+    let zim = Zim::new(test_zim::sample_zim("encoded_len")).ok().unwrap();
+    // the test archives write their directory entries back to back, in url order
+    for idx in 0..zim.article_count - 1 {
+        let entry = zim.get_by_url_index(idx).unwrap();
+        let len = zim.url_offset(idx + 1) - zim.url_offset(idx);
+        assert_eq!(entry.encoded_len() as u64, len);
+    }
+    // a redirect has no blob number, so it's 4 bytes shorter than an article with the same strings
+    assert_eq!(zim.get_by_url('A', "Baz").unwrap().encoded_len(), 2 + 1 + 1 + 4 + 4 + 4 + 4);
+}
+
+#[test]
+fn test_extended_cluster() {
+    use test_zim::{Content, TestEntry};
+    // an extended cluster with 8-byte blob offsets, compressed with python's lzma module
+    const CLUSTER: &[u8] = b"\xfd7zXZ\x00\x00\x04\xe6\xd6\xb4F\x02\x00!\x01\x16\x00\x00\x00t/\xe5\xa3\xe0\x00/\x00\x1d]\x00\x0c\
+                             \x004S3\x8f7>\x19,\xf3\x12\xa3\x0f\xde&Tl\xe4\xb6\xae\xa6\xa0\xdb\xc7\xea,\xe4i\x00\x00\x00\x00\
+                             \x05\x82\x0c(\xbd\x02U%\x00\x0190=\x08\xdb\x08\x1f\xb6\xf3}\x01\x00\x00\x00\x00\x04YZ";
+    let entries = || vec![
+        TestEntry { namespace: 'A', url: "Bar", title: "Bar", revision: 0, content: Content::Data("text/plain", b"extended bar") },
+        TestEntry { namespace: 'A', url: "Foo", title: "Foo", revision: 0, content: Content::Data("text/plain", b"extended foo") },
+    ];
+    // the extended flag is in the high bits, and XZ (4) in the low bits
+    let path = test_zim::write_zim_compressed("extended_cluster", entries(), None, &|_| (0x14, CLUSTER.to_vec()));
+    let zim = Zim::new(path).ok().unwrap();
+    let cluster = zim.get_cluster(0).unwrap();
+    assert_eq!(cluster.compression(), Compression::Xz);
+    assert!(cluster.is_extended());
+    assert_eq!(cluster.blob_offsets(), &[24, 36, 48]);
+    assert_eq!(cluster.get_blob(1), b"extended foo");
+    let entry = zim.get_by_url('A', "Bar").unwrap();
+    assert_eq!(zim.read_blob(&entry).ok().unwrap().data, b"extended bar");
+    assert_eq!(zim.read_blob_partial(0, 1).ok().unwrap(), b"extended foo");
+    let mut buf = Vec::new();
+    assert_eq!(zim.decompress_cluster_into(0, &mut buf).ok().unwrap().get_blob(0), b"extended bar");
+    assert_eq!(zim.total_decompressed_size().ok().unwrap(), 48);
+
+    // an uncompressed extended cluster, made by widening the offsets of the usual one
+    let path = test_zim::write_zim_compressed("extended_cluster_plain", entries(), None, &|cluster| {
+        let mut cur = Cursor::new(cluster);
+        let count = cur.read_u32::<LittleEndian>().unwrap() / 4;
+        let mut widened = Vec::new();
+        for i in 0..count {
+            let off = if i == 0 { count * 4 } else { cur.read_u32::<LittleEndian>().unwrap() };
+            widened.write_u64::<LittleEndian>((off + count * 4) as u64).unwrap();
+        }
+        widened.extend_from_slice(&cluster[count as usize * 4..]);
+        (0x11, widened)
+    });
+    let zim = Zim::open_checked(path).ok().unwrap();
+    assert_eq!(zim.get_cluster(0).unwrap().compression(), Compression::None);
+    assert!(zim.get_cluster(0).unwrap().is_extended());
+    let view = zim.cluster_view(0).unwrap();
+    assert!(view.is_borrowed());
+    assert_eq!(view.get_blob(1), b"extended foo");
+    assert_eq!(zim.read_blob_partial(0, 0).ok().unwrap(), b"extended bar");
+}
+
+#[test]
+fn test_bzip2_cluster() {
+    use test_zim::{Content, TestEntry};
+    // the cluster below, compressed with python's bz2 module
+    const CLUSTER: &[u8] = b"BZh91AY&SY\xe1y\x16\x8f\x00\x00\x03y\x80@@\x00 @\x00\x10\x00\x10 @\x10 \x001\x06L@\
+                             \xc2\x06\x89\xb0-!\xc7\x05C\x0c>.\xe4\x8ap\xa1!\xc2\xf2-\x1e";
+    let entries = vec![
+        TestEntry { namespace: 'A', url: "Foo", title: "Foo", revision: 0, content: Content::Data("text/plain", b"bzip2 bzip2 bzip2") },
+    ];
+    let path = test_zim::write_zim_compressed("bzip2_cluster", entries, None, &|cluster| {
+        assert_eq!(bzip2::decompress(CLUSTER).unwrap(), cluster);
+        (3, CLUSTER.to_vec())
+    });
+    let zim = Zim::new(path).ok().unwrap();
+    assert!(zim.uses_compression(Compression::Bzip2));
+    let entry = zim.get_by_url('A', "Foo").unwrap();
+    assert_eq!(zim.read_blob(&entry).ok().unwrap().data, b"bzip2 bzip2 bzip2");
+    assert_eq!(zim.get_cluster(0).unwrap().get_blob(0), b"bzip2 bzip2 bzip2");
+    let view = zim.cluster_view(0).unwrap();
+    assert!(!view.is_borrowed());
+    assert_eq!(view.get_blob(0), b"bzip2 bzip2 bzip2");
+    assert_eq!(zim.total_decompressed_size().ok().unwrap(), 25);
+    let ratio = 25.0 / (CLUSTER.len() + 1) as f64;
+    assert_eq!(zim.get_cluster(0).unwrap().compression_ratio(), ratio);
+    assert_eq!(zim.average_compression_ratio(1).ok().unwrap(), ratio);
+}
+
+#[test]
+fn test_zlib_cluster() {
+    use test_zim::{Content, TestEntry};
+    // the cluster below, compressed with python's zlib module
+    const CLUSTER: &[u8] = b"x\xda\xe3```\x10\x03\xe2\xaa\x9c\xcc$\x058\x01\x00*\xb1\x05r";
+    let entries = vec![
+        TestEntry { namespace: 'A', url: "Foo", title: "Foo", revision: 0, content: Content::Data("text/plain", b"zlib zlib zlib") },
+    ];
+    let path = test_zim::write_zim_compressed("zlib_cluster", entries, None, &|cluster| {
+        assert_eq!(miniz_oxide::inflate::decompress_to_vec_zlib(CLUSTER).unwrap(), cluster);
+        (2, CLUSTER.to_vec())
+    });
+    let zim = Zim::new(path).ok().unwrap();
+    assert!(zim.uses_compression(Compression::Zlib));
+    let entry = zim.get_by_url('A', "Foo").unwrap();
+    assert_eq!(zim.read_blob(&entry).ok().unwrap().data, b"zlib zlib zlib");
+    assert_eq!(zim.read_blob_partial(0, 0).ok().unwrap(), b"zlib zlib zlib");
+    assert_eq!(zim.get_cluster(0).unwrap().decompressed_len(), 22);
+    assert_eq!(zim.total_decompressed_size().ok().unwrap(), 22);
+    assert_eq!(zim.average_compression_ratio(1).ok().unwrap(), 22.0 / (CLUSTER.len() + 1) as f64);
+
+    let mut buf = Vec::with_capacity(64);
+    let ptr = buf.as_ptr();
+    for _ in 0..2 {
+        let view = zim.decompress_cluster_into(0, &mut buf).ok().unwrap();
+        assert_eq!(view.get_blob(0), b"zlib zlib zlib");
+    }
+    // the buffer's allocation was reused rather than replaced
+    assert_eq!(buf.len(), 22);
+    assert_eq!(buf.as_ptr(), ptr);
+}
+
+#[test]
+fn test_zlib_sample() {
+    let path = test_zim::write_zim_compressed("zlib_sample", test_zim::sample_entries(), None, &|cluster| {
+        (2, miniz_oxide::deflate::compress_to_vec_zlib(cluster, 6))
+    });
+    let zim = Zim::new(&path).ok().unwrap();
+    assert!(zim.compression_types().into_iter().eq(Some(Compression::Zlib)));
+    let entry = zim.get_by_url('A', "Caf\u{e9}").unwrap();
+    assert_eq!(zim.read_blob(&entry).ok().unwrap().data, b"<p>cafe</p>");
+    let title = zim.get_by_url('M', "Title").unwrap();
+    assert_eq!(zim.read_blob(&title).ok().unwrap().data, b"Test");
+    assert_eq!(zim.read_blob_partial(0, 5).ok().unwrap(), b"Test");
+    let mut buf = b"leftover".to_vec();
+    assert_eq!(zim.decompress_cluster_into(0, &mut buf).ok().unwrap().get_blob(0), b"<p>bar</p>");
+
+    // a corrupt or truncated stream is a Decompress error, not garbage
+    let mut data = std::fs::read(&path).unwrap();
+    let (start, end) = zim.cluster_range(0);
+    data[(start + end) as usize / 2] ^= 0x55;
+    let zim = Zim::new(test_zim::write_file("zlib_sample_corrupt", &data)).ok().unwrap();
+    assert_eq!(Cluster::new(&zim, 0).err().unwrap().kind(), ParseErrorKind::Decompress);
+    let mut data = std::fs::read(&path).unwrap();
+    data.drain(end as usize - 4..end as usize);
+    let zim = Zim::new(test_zim::write_file("zlib_sample_truncated", &data)).ok().unwrap();
+    assert!(Cluster::new(&zim, 0).is_err());
+}
+
+#[test]
+fn test_revision() {
+    let zim = Zim::new(test_zim::sample_zim("revision")).ok().unwrap();
+    assert_eq!(zim.get_by_url('M', "Counter").unwrap().revision(), 3);
+    assert_eq!(zim.get_by_url('M', "Title").unwrap().revision(), 0);
+}
+
+#[test]
+fn test_locate() {
+    let zim = Zim::new(test_zim::sample_zim("locate")).ok().unwrap();
+    // A/Bar, A/Baz, A/Café, A/Foo, I/logo.png, M/Counter, M/Title
+    assert_eq!(zim.locate('A', "Bar"), Ok(0));
+    assert_eq!(zim.locate('A', "Foo"), Ok(3));
+    assert_eq!(zim.locate('M', "Title"), Ok(6));
+    assert_eq!(zim.locate('A', "Aardvark"), Err(0));
+    assert_eq!(zim.locate('A', "Bat"), Err(1));
+    assert_eq!(zim.locate('B', "Foo"), Err(4));
+    assert_eq!(zim.locate('Z', "Foo"), Err(7));
+}
+
+#[test]
+fn test_data_end() {
+    let path = test_zim::sample_zim("data_end");
+    let zim = Zim::new(&path).ok().unwrap();
+    assert_eq!(zim.file_len(), std::fs::metadata(&path).unwrap().len());
+    assert_eq!(zim.data_end() + 16, zim.file_len());
+    assert_eq!(zim.raw_header(), &std::fs::read(&path).unwrap()[..80]);
+}
+
+#[test]
+fn test_open_buffered() {
+    let zim = Zim::open_with(test_zim::sample_zim("open_buffered"),
+                             ZimOptions::new().buffered(true).validate_ranges(true).validate_checksum(true)).ok().unwrap();
+    assert_eq!(zim.len(), 7);
+    let entry = zim.get_by_url('A', "Foo").unwrap();
+    assert_eq!(zim.read_blob(&entry).ok().unwrap().data, b"<p>foo</p>");
+    zim.advise_sequential();
+}
+
+#[test]
+fn test_advice_restored() {
+    let zim = Zim::new(test_zim::sample_zim("advice_restored")).ok().unwrap();
+    assert_eq!(zim.advice.get(), Advice::Normal);
+    zim.for_each_article_in_cluster_order(|_, _| assert_eq!(zim.advice.get(), Advice::Sequential)).ok().unwrap();
+    assert_eq!(zim.advice.get(), Advice::Normal);
+
+    zim.advise_random();
+    {
+        let mut articles = zim.iter_article_content();
+        assert!(articles.next().is_some());
+        assert_eq!(zim.advice.get(), Advice::Sequential);
+    }
+    assert_eq!(zim.advice.get(), Advice::Random);
+    let dir = std::env::temp_dir().join(format!("zim-test-{}-advice_restored", std::process::id()));
+    zim.extract_to(&dir).ok().unwrap();
+    assert_eq!(zim.advice.get(), Advice::Random);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_cluster_view() {
+    let zim = Zim::new(test_zim::sample_zim("cluster_view")).ok().unwrap();
+    let view = zim.cluster_view(0).unwrap();
+    assert!(view.is_borrowed());
+    assert_eq!(view.get_blob(0), b"<p>bar</p>");
+    assert_eq!(view.get_blob_checked(6).err().unwrap().cluster_index(), Some(0));
+    assert!(zim.cluster_view(1).is_none());
+}
+
+#[test]
+fn test_namespaces() {
+    let zim = Zim::new(test_zim::sample_zim("namespaces")).ok().unwrap();
+    assert_eq!(zim.namespaces(), vec!['A', 'I', 'M']);
+    for &ns in &['A', 'I', 'M'] {
+        assert!(zim.has_namespace(ns));
+    }
+    for &ns in &['-', 'B', 'J', 'X'] {
+        assert!(!zim.has_namespace(ns));
+    }
+    let empty = Zim::new(test_zim::write_zim("namespaces_empty", Vec::new(), None)).ok().unwrap();
+    assert!(empty.namespaces().is_empty());
+    assert!(!empty.has_namespace('A'));
+}
+
+#[test]
+fn test_mime_list_offset() {
+    // the padding isn't a valid mime list, so this only works if the parser seeks to the offset
+    // given in the header
+    let zim = Zim::open_with(test_zim::write_zim_with_mime_gap("mime_list_offset", test_zim::sample_entries(), 13),
+                             ZimOptions::new().validate_ranges(true).validate_checksum(true)).ok().unwrap();
+    assert_eq!(zim.get_mimetype(0), Some(MimeType::Type("text/html".to_owned())));
+    assert_eq!(zim.head('I', "logo.png"), Some(MimeType::Type("image/png".to_owned())));
+}
+
+#[test]
+fn test_open_checked() {
+    use byteorder::{LittleEndian, WriteBytesExt};
+    let sample = std::fs::read(test_zim::sample_zim("open_checked")).unwrap();
+    let zim = Zim::open_checked(test_zim::write_file("open_checked_ok", &sample)).ok().unwrap();
+    assert_eq!(zim.len(), 7);
+
+    let open_modified = |name: &str, f: &dyn Fn(&mut Vec<u8>)| {
+        let mut data = sample.clone();
+        f(&mut data);
+        Zim::open_checked(test_zim::write_file(name, &data))
+    };
+    let write_u32 = |data: &mut Vec<u8>, off: usize, val: u32| (&mut data[off..]).write_u32::<LittleEndian>(val).unwrap();
+    let write_u64 = |data: &mut Vec<u8>, off: usize, val: u64| (&mut data[off..]).write_u64::<LittleEndian>(val).unwrap();
+    let cluster_ptr_pos = zim.cluster_tbl_off as usize;
+    let cluster_pos = zim.cluster_offset(0) as usize;
+
+    assert_eq!(open_modified("open_checked_short", &|d| d.truncate(40)).err().unwrap().kind(), ParseErrorKind::Truncated);
+    assert!(open_modified("open_checked_articles", &|d| write_u32(d, 24, 0xffffffff)).is_err());
+    assert!(open_modified("open_checked_clusters", &|d| write_u32(d, 28, 2)).is_err());
+    assert!(open_modified("open_checked_cluster_ptr", &|d| write_u64(d, cluster_ptr_pos, 1 << 40)).is_err());
+    assert!(open_modified("open_checked_mime_list", &|d| write_u64(d, 56, 1 << 40)).is_err());
+    assert!(open_modified("open_checked_blob_table", &|d| write_u32(d, cluster_pos + 1, 3)).is_err());
+    assert!(read_c_string(&mut Cursor::new(b"unterminated")).is_err());
+
+    // without validation, a broken cluster table gives errors when the cluster is read instead
+    let mut data = sample.clone();
+    write_u64(&mut data, cluster_ptr_pos, zim.checksum_off);
+    let unchecked = Zim::new(test_zim::write_file("open_checked_unchecked", &data)).ok().unwrap();
+    assert!(unchecked.get_cluster(0).is_none());
+    assert!(unchecked.read_blob(&unchecked.get_by_url('A', "Foo").unwrap()).is_err());
+
+    // flipping any single byte mustn't cause a panic, whether or not the file still opens
+    for pos in 0..sample.len() {
+        let zim = match open_modified("open_checked_flip", &|d| d[pos] ^= 0xff) {
+            Ok(zim) => zim,
+            Err(_) => continue
+        };
+        for entry in zim.iterate_by_urls() {
+            let _ = zim.read_blob(&entry);
+            let _ = zim.resolve_entry(&entry);
+        }
+        for idx in 0..zim.cluster_count {
+            let _ = zim.get_cluster(idx).map(|c| c.get_blob_checked(0).is_ok());
+        }
+    }
+}
+
+#[test]
+fn test_iter_by_urls_enumerated() {
+    let zim = Zim::new(test_zim::sample_zim("iter_by_urls_enumerated")).ok().unwrap();
+    let entries: Vec<(u32, DirectoryEntry)> = zim.iter_by_urls_enumerated().collect();
+    assert_eq!(entries.len(), 7);
+    for (idx, entry) in entries {
+        assert_eq!(zim.get_by_url_index(idx).unwrap().url, entry.url);
+    }
+    let (idx, entry) = zim.iter_by_urls_enumerated().next_back().unwrap();
+    assert_eq!((idx, entry.url.as_str()), (6, "Title"));
+}
+
+#[test]
+fn test_main_page_content() {
+    let zim = Zim::new(test_zim::write_zim("main_page_content", test_zim::sample_entries(), Some(('A', "Baz")))).ok().unwrap();
+    let (mime, data) = zim.main_page_content().unwrap();
+    assert_eq!(mime, MimeType::Type("text/html".to_owned()));
+    assert_eq!(data, b"<p>foo</p>");
+    assert_eq!(zim.main_page_url(), Some(('A', "Baz".to_owned())));
+
+    let zim = Zim::new(test_zim::write_zim("main_page_content_none", test_zim::sample_entries(), None)).ok().unwrap();
+    assert!(zim.main_page_content().is_none());
+    assert!(zim.main_page_url().is_none());
+
+    // a main page index past the end of the url table
+    let mut data = std::fs::read(test_zim::sample_zim("main_page_content_bad")).unwrap();
+    data[64] = 100;
+    let zim = Zim::new(test_zim::write_file("main_page_content_bad_changed", &data)).ok().unwrap();
+    assert_eq!(zim.main_page_idx, Some(100));
+    assert!(zim.main_page_url().is_none());
+    assert!(zim.main_page_content().is_none());
+}
+
+#[test]
+fn test_title_order() {
+    let path = test_zim::sample_zim("title_order");
+    for &lazy in &[false, true] {
+        let zim = Zim::open_with(&path, ZimOptions::new().lazy_tables(lazy)).ok().unwrap();
+        let titles: Vec<String> = zim.title_order().iter().map(|&idx| zim.get_by_url_index(idx).unwrap().title).collect();
+        assert_eq!(titles, vec!["Bar", "Baz", "Caf\u{e9}", "Foo", "logo.png", "Counter", "Title"]);
+    }
+}
+
+#[test]
+fn test_unknown_mimetype() {
+    use byteorder::{LittleEndian, WriteBytesExt};
+    let mut data = std::fs::read(test_zim::sample_zim("unknown_mimetype")).unwrap();
+    let zim = Zim::new(test_zim::write_file("unknown_mimetype_orig", &data)).ok().unwrap();
+    let idx = zim.locate('A', "Foo").unwrap();
+    (&mut data[zim.url_offset(idx) as usize..]).write_u16::<LittleEndian>(200).unwrap();
+
+    let zim = Zim::new(test_zim::write_file("unknown_mimetype_changed", &data)).ok().unwrap();
+    let entry = zim.get_by_url('A', "Foo").unwrap();
+    assert_eq!(entry.mime_type, MimeType::Unknown(200));
+    assert_eq!(zim.get_mimetype(200), Some(MimeType::Unknown(200)));
+    assert_eq!(zim.get_mimetype(0xffff), Some(MimeType::Redirect));
+    assert_eq!(zim.get_mimetype(0xfffd), Some(MimeType::DeletedEntry));
+    assert_eq!(zim.get_mimetype(0xfffc), Some(MimeType::Unknown(0xfffc)));
+    assert_eq!(zim.get_mimetype(3), Some(MimeType::Unknown(3)));
+    assert_eq!(zim.mime_type_count(), 3);
+    assert_eq!(zim.get_mimetype(1), Some(MimeType::Type("image/png".to_owned())));
+    assert_eq!(zim.get_mimetype(1), Some(MimeType::from("image/png")));
+    assert_eq!(zim.get_mimetype(2), Some(MimeType::from("text/plain".to_owned())));
+    assert!(MimeType::from("Redirect") != MimeType::Redirect);
+    assert_eq!(zim.read_blob(&entry).ok().unwrap().data, b"<p>foo</p>");
+    // the entries after it are still there
+    assert_eq!(zim.iterate_by_urls().count(), 7);
+}
+
+#[test]
+fn test_metadata_entries() {
+    let zim = Zim::new(test_zim::sample_zim("metadata_entries")).ok().unwrap();
+    let metadata: Vec<(String, Vec<u8>)> = zim.metadata_entries().collect();
+    assert_eq!(metadata, vec![("Counter".to_owned(), b"text/html=3".to_vec()), ("Title".to_owned(), b"Test".to_vec())]);
+}
+
+#[test]
+fn test_languages() {
+    use test_zim::{Content, TestEntry};
+    let entries = vec![
+        TestEntry { namespace: 'M', url: "Language", title: "Language", revision: 0, content: Content::Data("text/plain", b"eng;fra, deu,") },
+    ];
+    let zim = Zim::new(test_zim::write_zim("languages", entries, None)).ok().unwrap();
+    assert_eq!(zim.languages(), vec!["eng", "fra", "deu"]);
+
+    let zim = Zim::new(test_zim::sample_zim("languages_none")).ok().unwrap();
+    assert!(zim.languages().is_empty());
+}
+
+#[test]
+fn test_illustration_sized() {
+    use test_zim::{Content, TestEntry};
+    let entries = vec![
+        TestEntry { namespace: 'M', url: "Illustration_96x96@1", title: "", revision: 0, content: Content::Data("image/png", b"96") },
+        TestEntry { namespace: 'M', url: "Illustration_48x48@1", title: "", revision: 0, content: Content::Data("image/png", b"48") },
+        TestEntry { namespace: 'M', url: "Illustration_48x48@2", title: "", revision: 0, content: Content::Data("image/png", b"48@2") },
+        TestEntry { namespace: 'M', url: "Illustration_64x32@1", title: "", revision: 0, content: Content::Data("image/png", b"64x32") },
+        TestEntry { namespace: 'M', url: "Illustration_bad@1", title: "", revision: 0, content: Content::Data("image/png", b"bad") },
+    ];
+    let zim = Zim::new(test_zim::write_zim("illustration_sized", entries, None)).ok().unwrap();
+    assert_eq!(zim.available_illustration_sizes(), vec![48, 96]);
+    assert_eq!(zim.illustration_sized(48).unwrap(), b"48");
+    assert_eq!(zim.illustration_sized(96).unwrap(), b"96");
+    assert!(zim.illustration_sized(32).is_none());
+
+    let zim = Zim::new(test_zim::sample_zim("illustration_sized_none")).ok().unwrap();
+    assert!(zim.available_illustration_sizes().is_empty());
+    assert!(zim.illustration_sized(48).is_none());
+}
+
+#[cfg(feature = "sha2")]
+#[test]
+fn test_diff() {
+    use test_zim::{Content, TestEntry};
+    let zim = Zim::new(test_zim::sample_zim("diff")).ok().unwrap();
+    assert!(zim.content_equivalent(&zim));
+
+    // the same articles, but at different blob indices, and with the other namespaces changed
+    let mut entries = test_zim::sample_entries();
+    entries.retain(|e| e.namespace == 'A');
+    entries.push(TestEntry { namespace: '-', url: "favicon", title: "favicon", revision: 0, content: Content::Data("image/png", b"icon") });
+    let same = Zim::new(test_zim::write_zim("diff_same", entries, None)).ok().unwrap();
+    assert!(zim.content_equivalent(&same));
+    assert!(same.content_equivalent(&zim));
+
+    let mut entries = test_zim::sample_entries();
+    entries.retain(|e| e.url != "Bar");
+    for entry in &mut entries {
+        if entry.url == "Foo" {
+            entry.content = Content::Data("text/html", b"<p>changed</p>");
+        } else if entry.url == "Baz" {
+            entry.content = Content::Redirect('A', "Caf\u{e9}");
+        }
+    }
+    entries.push(TestEntry { namespace: 'A', url: "New", title: "New", revision: 0, content: Content::Data("text/html", b"new") });
+    let other = Zim::new(test_zim::write_zim("diff_other", entries, None)).ok().unwrap();
+    let diff = zim.diff(&other);
+    assert_eq!(diff, ArchiveDiff {
+        added: vec!["New".to_owned()],
+        removed: vec!["Bar".to_owned()],
+        changed: vec!["Baz".to_owned(), "Foo".to_owned()],
+    });
+    assert!(!zim.content_equivalent(&other));
+}
+
+#[test]
+fn test_article_count_in_namespace() {
+    let zim = Zim::new(test_zim::sample_zim("article_count_in_namespace")).ok().unwrap();
+    assert_eq!(zim.article_count_in_namespace('A'), 4);
+    assert_eq!(zim.article_count_in_namespace('I'), 1);
+    assert_eq!(zim.article_count_in_namespace('X'), 0);
+    for ns in zim.namespaces() {
+        let entries = zim.collect_namespace(ns);
+        assert_eq!(entries.len() as u32, zim.article_count_in_namespace(ns));
+        assert!(entries.iter().all(|e| e.namespace == ns));
+    }
+    assert!(zim.collect_namespace('X').is_empty());
+    let total: u32 = zim.namespaces().into_iter().map(|ns| zim.article_count_in_namespace(ns)).sum();
+    assert_eq!(total, zim.article_count);
+}
+
+#[test]
+fn test_entries_in_cluster() {
+    let zim = Zim::new(test_zim::sample_zim("entries_in_cluster")).ok().unwrap();
+    let urls: Vec<String> = zim.entries_in_cluster(0).into_iter().map(|e| e.url).collect();
+    assert_eq!(urls, vec!["Bar", "Caf\u{e9}", "Foo", "logo.png", "Counter", "Title"]);
+    assert!(zim.entries_in_cluster(1).is_empty());
+    assert_eq!(zim.cluster_entry_map().len(), 1);
+}
+
+#[cfg(feature = "sha2")]
+#[test]
+fn test_article_digest() {
+    use test_zim::{Content, TestEntry};
+    let entries = vec![
+        TestEntry { namespace: 'A', url: "One", title: "One", revision: 0, content: Content::Data("text/plain", b"same") },
+        TestEntry { namespace: 'A', url: "Two", title: "Two", revision: 0, content: Content::Data("text/plain", b"same") },
+        TestEntry { namespace: 'A', url: "Three", title: "Three", revision: 0, content: Content::Data("text/plain", b"different") },
+        TestEntry { namespace: 'A', url: "Four", title: "Four", revision: 0, content: Content::Redirect('A', "One") },
+    ];
+    let zim = Zim::new(test_zim::write_zim("article_digest", entries, None)).ok().unwrap();
+    let digest = |url| zim.article_digest(&zim.get_by_url('A', url).unwrap());
+    assert_eq!(digest("One"), digest("Two"));
+    assert!(digest("One") != digest("Three"));
+    assert!(digest("Four").is_none());
+
+    let all = zim.digest_all();
+    assert_eq!(all.len(), 3);
+    assert_eq!(all.get(&zim.locate('A', "Three").unwrap()), digest("Three").as_ref());
+}
+
+#[test]
+fn test_bloated_article_count() {
+    use byteorder::{LittleEndian, WriteBytesExt};
+    let mut data = std::fs::read(test_zim::sample_zim("bloated_article_count")).unwrap();
+    (&mut data[24..]).write_u32::<LittleEndian>(0x7fffffff).unwrap();
+    let path = test_zim::write_file("bloated_article_count_changed", &data);
+    for &lazy in &[false, true] {
+        let err = Zim::open_with(&path, ZimOptions::new().lazy_tables(lazy)).err().unwrap();
+        assert_eq!(err.kind(), ParseErrorKind::OutOfRange);
+        assert_eq!(err.offset(), Some(24));
+    }
+}
+
+#[test]
+fn test_sort_key() {
+    let zim = Zim::new(test_zim::sample_zim("sort_key")).ok().unwrap();
+    let entries: Vec<DirectoryEntry> = zim.iterate_by_urls().collect();
+    for pair in entries.windows(2) {
+        assert!(pair[0].sort_key() <= pair[1].sort_key());
+        assert!(pair[0] < pair[1]);
+    }
+    let mut shuffled: Vec<DirectoryEntry> = entries.iter().rev().cloned().collect();
+    shuffled.sort();
+    assert_eq!(shuffled, entries);
+}
+
+#[test]
+fn test_verify_sorted() {
+    let mut data = std::fs::read(test_zim::sample_zim("verify_sorted")).unwrap();
+    let zim = Zim::new(test_zim::write_file("verify_sorted_orig", &data)).ok().unwrap();
+    assert!(zim.verify_sorted().is_ok());
+
+    // swap the url table entries for A/Bar and A/Baz
+    let (url_tbl, title_tbl) = (zim.url_tbl_off as usize, zim.title_tbl_off as usize);
+    let mut swapped = data.clone();
+    for i in 0..8 {
+        swapped.swap(url_tbl + i, url_tbl + 8 + i);
+    }
+    let zim = Zim::new(test_zim::write_file("verify_sorted_urls", &swapped)).ok().unwrap();
+    let err = zim.verify_sorted().err().unwrap();
+    assert_eq!(err.kind(), ParseErrorKind::Unsorted);
+    assert_eq!(err.url_index(), Some(1));
+
+    // and the title table entries for the same two
+    for i in 0..4 {
+        data.swap(title_tbl + i, title_tbl + 4 + i);
+    }
+    let zim = Zim::new(test_zim::write_file("verify_sorted_titles", &data)).ok().unwrap();
+    let err = zim.verify_sorted().err().unwrap();
+    assert_eq!(err.kind(), ParseErrorKind::Unsorted);
+    assert_eq!(err.offset(), Some(title_tbl as u64 + 4));
+
+    // an entry with an empty title sorts by its url
+    let mut entries = test_zim::sample_entries();
+    entries.push(test_zim::TestEntry { namespace: 'A', url: "Zed", title: "", revision: 0, content: test_zim::Content::Data("text/html", b"<p>zed</p>") });
+    let zim = Zim::new(test_zim::write_zim("verify_sorted_empty_title", entries, None)).ok().unwrap();
+    assert_eq!(zim.read_entry(zim.title_entry(4)).ok().unwrap().url, "Zed");
+    assert!(zim.verify_sorted().is_ok());
+}
+
+#[test]
+fn test_no_checksum() {
+    let mut data = std::fs::read(test_zim::sample_zim("no_checksum")).unwrap();
+    let len = data.len();
+    data.truncate(len - 16);
+    for b in &mut data[72..80] {
+        *b = 0;
+    }
+    let path = test_zim::write_file("no_checksum_changed", &data);
+    let zim = Zim::open_checked(&path).ok().unwrap();
+    assert_eq!(zim.data_end(), zim.file_len());
+    // M/Title's data is the last blob of the last cluster, so it runs right up to the end of the file
+    let entry = zim.get_by_url('M', "Title").unwrap();
+    assert_eq!(zim.read_blob(&entry).ok().unwrap().data, b"Test");
+    assert_eq!(zim.get_cluster(0).unwrap().get_blob(5), b"Test");
+    // there's no checksum to verify, so asking for it to be verified isn't an error
+    let zim = Zim::open_with(&path, ZimOptions::new().validate_checksum(true)).ok().unwrap();
+    assert_eq!(zim.get_cluster(0).unwrap().get_blob(5), b"Test");
+}
+
+#[test]
+fn test_validate_clusters() {
+    let path = test_zim::sample_zim("validate_clusters");
+    assert!(Zim::open_with(&path, ZimOptions::new().validate_clusters(true)).is_ok());
+
+    let mut data = std::fs::read(&path).unwrap();
+    let off = Zim::new(&path).ok().unwrap().cluster_offset(0);
+    data[off as usize] = 9;
+    let path = test_zim::write_file("validate_clusters_bad", &data);
+    let err = Zim::open_with(&path, ZimOptions::new().validate_clusters(true)).err().unwrap();
+    assert_eq!(err.kind(), ParseErrorKind::Decompress);
+    assert_eq!(err.cluster_index(), Some(0));
+    assert_eq!(err.offset(), Some(off));
+    // it's off by default
+    assert!(Zim::new(&path).is_ok());
+
+    // zstd is a known type, but it can't be decompressed
+    data[off as usize] = 5;
+    let path = test_zim::write_file("validate_clusters_zstd", &data);
+    let err = Zim::open_with(&path, ZimOptions::new().validate_clusters(true)).err().unwrap();
+    assert_eq!(err.kind(), ParseErrorKind::Decompress);
+    assert_eq!(err.cluster_index(), Some(0));
+}
+
+#[test]
+fn test_unsupported_compression() {
+    let path = test_zim::sample_zim("unsupported_compression");
+    let mut data = std::fs::read(&path).unwrap();
+    let off = Zim::new(&path).ok().unwrap().cluster_offset(0) as usize;
+    // the cluster's bytes are uncompressed, so reading them as such would "work"
+    for &byte in &[5, 9, 0x15] {
+        data[off] = byte;
+        let zim = Zim::new(test_zim::write_file("unsupported_compression_patched", &data)).ok().unwrap();
+        assert!(zim.get_cluster(0).is_none());
+        assert_eq!(Cluster::new(&zim, 0).err().unwrap().kind(), ParseErrorKind::Decompress);
+        assert_eq!(zim.read_blob_partial(0, 5).err().unwrap().kind(), ParseErrorKind::Decompress);
+        assert_eq!(zim.total_decompressed_size().err().unwrap().kind(), ParseErrorKind::Decompress);
+        assert_eq!(zim.decompress_cluster_into(0, &mut Vec::new()).err().unwrap().kind(), ParseErrorKind::Decompress);
+        assert!(zim.cluster_view(0).is_none());
+        assert!(zim.get_by_url('A', "Foo").map(|e| zim.read_blob(&e)).unwrap().is_err());
+    }
+}
+
+#[test]
+fn test_cluster_for() {
+    let zim = Zim::new(test_zim::sample_zim("cluster_for")).ok().unwrap();
+    let entry = zim.get_by_url('A', "Bar").unwrap();
+    let cluster = zim.cluster_for(&entry).unwrap();
+    assert_eq!(cluster.get_blob(0), b"<p>bar</p>");
+    assert!(zim.cluster_for(&zim.get_by_url('A', "Baz").unwrap()).is_none());
+}
+
+#[test]
+fn test_iterate_by_titles() {
+    let zim = Zim::new(test_zim::sample_zim("iterate_by_titles")).ok().unwrap();
+    let titles = |iter: &mut dyn Iterator<Item=Result<DirectoryEntry, ParsingError>>| {
+        iter.map(|e| e.ok().unwrap().title).collect::<Vec<String>>()
+    };
+    let forward = titles(&mut zim.iterate_by_titles());
+    assert_eq!(forward, vec!["Bar", "Baz", "Caf\u{e9}", "Foo", "logo.png", "Counter", "Title"]);
+    let mut backward = titles(&mut zim.iterate_by_titles().rev());
+    backward.reverse();
+    assert_eq!(forward, backward);
+
+    let mut iter = zim.iterate_by_titles();
+    assert_eq!(iter.len(), 7);
+    assert_eq!(iter.nth(2).unwrap().ok().unwrap().title, "Caf\u{e9}");
+    assert_eq!(iter.next_back().unwrap().ok().unwrap().title, "Title");
+    assert_eq!(iter.len(), 3);
+    assert_eq!(titles(&mut iter), vec!["Foo", "logo.png", "Counter"]);
+    assert!(zim.iterate_by_titles().nth(7).is_none());
+
+    // point the url table entry for A/Foo past the end of the file
+    let mut data = std::fs::read(test_zim::sample_zim("iterate_by_titles_bad")).unwrap();
+    let (off, len) = (zim.url_tbl_off as usize + 3 * 8, data.len() as u64);
+    (&mut data[off..]).write_u64::<LittleEndian>(len + 100).unwrap();
+    let zim = Zim::new(test_zim::write_file("iterate_by_titles_bad_patched", &data)).ok().unwrap();
+    let iter = zim.iterate_by_titles();
+    assert_eq!(iter.len(), 7);
+    let results: Vec<_> = iter.collect();
+    assert_eq!(results.len(), 7);
+    assert!(results[3].is_err());
+    assert_eq!(results.iter().filter(|r| r.is_err()).count(), 1);
+}
+
+#[test]
+fn test_read_blob_at() {
+    let zim = Zim::new(test_zim::sample_zim("read_blob_at")).ok().unwrap();
+    let entry = zim.get_by_url('A', "Foo").unwrap();
+    if let Some(Target::Cluster(cid, bid)) = entry.target {
+        assert_eq!(zim.read_blob_at(cid, bid).unwrap(), b"<p>foo</p>");
+    } else {
+        panic!("A/Foo should have data");
+    }
+    assert_eq!(zim.read_blob_at(0, 5).unwrap(), b"Test");
+    assert!(zim.read_blob_at(0, 6).is_none());
+    assert!(zim.read_blob_at(1, 0).is_none());
+}
+
+#[test]
+fn test_index_entries() {
+    let zim = Zim::new(test_zim::sample_zim("index_entries")).ok().unwrap();
+    let entries: Vec<(char, String, MimeType)> = zim.index_entries().collect();
+    let expected: Vec<(char, String, MimeType)> = zim.iterate_by_urls()
+        .map(|e| (e.namespace, e.url, e.mime_type))
+        .collect();
+    assert_eq!(entries.len(), 7);
+    assert_eq!(entries, expected);
+    assert_eq!(entries[1], ('A', "Baz".to_owned(), MimeType::Redirect));
+}
+
+#[test]
+fn test_compute_checksum() {
+    let path = test_zim::sample_zim("compute_checksum");
+    let data = std::fs::read(&path).unwrap();
+    let (contents, stored) = data.split_at(data.len() - 16);
+    let mut hasher = md5::Md5::new();
+    hasher.update(contents);
+    let expected: [u8; 16] = hasher.finalize().into();
+    assert_eq!(&expected[..], stored);
+
+    for &buffered in &[false, true] {
+        let zim = Zim::open_with(&path, ZimOptions::new().buffered(buffered)).ok().unwrap();
+        // chunk sizes that don't divide the file evenly, as well as one bigger than the file
+        for &chunk_size in &[1, 7, 64, CHECKSUM_CHUNK_SIZE] {
+            assert_eq!(zim.compute_checksum(chunk_size), Some(expected));
+        }
+        assert!(zim.checksum_matches());
+    }
+
+    let path = test_zim::write_file("compute_checksum_truncated", &data[..data.len() - 20]);
+    let zim = Zim::new(&path).ok().unwrap();
+    assert_eq!(zim.compute_checksum(CHECKSUM_CHUNK_SIZE), None);
+    assert!(!zim.checksum_matches());
+}
+
+#[test]
+fn test_index_of() {
+    let zim = Zim::new(test_zim::sample_zim("index_of")).ok().unwrap();
+    for (idx, entry) in zim.iter_by_urls_enumerated() {
+        assert_eq!(zim.index_of(&entry), Some(idx));
+        let found = zim.get_by_url_index(zim.index_of(&entry).unwrap()).unwrap();
+        assert_eq!(found, entry);
+        assert_eq!(found.target, entry.target);
+    }
+    let mut missing = zim.get_by_url('A', "Foo").unwrap();
+    missing.url = "Missing".to_owned();
+    assert_eq!(zim.index_of(&missing), None);
+}
+
+#[test]
+fn test_directory_entry_parse() {
+    let zim = Zim::new(test_zim::sample_zim("directory_entry_parse")).ok().unwrap();
+    for idx in 0..zim.article_count {
+        let raw = zim.raw_entry(idx).unwrap();
+        let parsed = DirectoryEntry::parse(raw, |id| zim.get_mimetype(id)).ok().unwrap();
+        let entry = zim.get_by_url_index(idx).unwrap();
+        assert_eq!((&parsed.mime_type, &parsed.title, &parsed.target), (&entry.mime_type, &entry.title, &entry.target));
+        assert_eq!(parsed, entry);
+        assert_eq!(parsed.encoded_len(), raw.len());
+    }
+
+    // without a mime list, ordinary ids are unknown but the reserved ones are still recognized
+    let bar = DirectoryEntry::parse(zim.raw_entry(0).unwrap(), |_| None).ok().unwrap();
+    assert_eq!(bar.mime_type, MimeType::Unknown(0));
+    let baz = DirectoryEntry::parse(zim.raw_entry(1).unwrap(), |_| None).ok().unwrap();
+    assert_eq!(baz.mime_type, MimeType::Redirect);
+    assert_eq!(baz.target, Some(Target::Redirect(3)));
+
+    let err = DirectoryEntry::parse(&zim.raw_entry(0).unwrap()[..20], |_| None).err().unwrap();
+    assert_eq!(err.kind(), ParseErrorKind::Truncated);
+}
+
+#[test]
+fn test_link_targets() {
+    use test_zim::{Content, TestEntry};
+    let mut entries = test_zim::sample_entries();
+    entries.push(TestEntry { namespace: 'A', url: "Link", title: "Link", revision: 0, content: Content::LinkTarget });
+    entries.push(TestEntry { namespace: 'B', url: "Other", title: "Other", revision: 0, content: Content::LinkTarget });
+    entries.push(TestEntry { namespace: 'A', url: "Gone", title: "Gone", revision: 0, content: Content::Deleted });
+    let zim = Zim::new(test_zim::write_zim("link_targets", entries, None)).ok().unwrap();
+    let targets: Vec<DirectoryEntry> = zim.link_targets().collect();
+    assert_eq!(targets.iter().map(|e| e.sort_key()).collect::<Vec<_>>(), vec![('A', "Link"), ('B', "Other")]);
+    assert!(targets.iter().all(|e| e.mime_type == MimeType::LinkTarget && e.target.is_none()));
+
+    let zim = Zim::new(test_zim::sample_zim("link_targets_none")).ok().unwrap();
+    assert_eq!(zim.link_targets().count(), 0);
+}
+
+#[test]
+fn test_decompress_cluster_into() {
+    let zim = Zim::new(test_zim::sample_zim("decompress_cluster_into")).ok().unwrap();
+    let mut buf = b"leftover".to_vec();
+    {
+        // uncompressed clusters are read in place
+        let view = zim.decompress_cluster_into(0, &mut buf).ok().unwrap();
+        assert!(view.is_borrowed());
+        assert_eq!(view.blob_count(), 6);
+        assert_eq!(view.get_blob(5), b"Test");
+    }
+    assert!(buf.is_empty());
+    let err = zim.decompress_cluster_into(1, &mut buf).err().unwrap();
+    assert_eq!(err.kind(), ParseErrorKind::OutOfRange);
+}
+
+#[test]
+fn test_urls_in_namespace() {
+    let zim = Zim::new(test_zim::sample_zim("urls_in_namespace")).ok().unwrap();
+    for ns in zim.namespaces() {
+        let urls: Vec<String> = zim.urls_in_namespace(ns).collect();
+        let expected: Vec<String> = zim.collect_namespace(ns).into_iter().map(|e| e.url).collect();
+        assert_eq!(urls, expected);
+    }
+    assert_eq!(zim.urls_in_namespace('A').collect::<Vec<String>>(), vec!["Bar", "Baz", "Caf\u{e9}", "Foo"]);
+    assert_eq!(zim.urls_in_namespace('X').count(), 0);
+}
+
+#[test]
+fn test_iter_range() {
+    let zim = Zim::new(test_zim::sample_zim("iter_range")).ok().unwrap();
+    let urls = |start, end| zim.iter_range(start, end).map(|e| e.url).collect::<Vec<String>>();
+    assert_eq!(urls(1, 3), vec!["Baz", "Caf\u{e9}"]);
+    assert_eq!(urls(5, 100), vec!["Counter", "Title"]);
+    assert!(urls(3, 3).is_empty());
+    assert!(urls(4, 2).is_empty());
+    assert_eq!(zim.iter_range(1, 3).next_back().unwrap().url, "Caf\u{e9}");
+}
+
+#[test]
+fn test_prefetch_clusters() {
+    let path = test_zim::sample_zim("prefetch_clusters");
+    let zim = Zim::open_with(&path, ZimOptions::new().cluster_cache_size(1)).ok().unwrap();
+    assert!(zim.cluster_cache.get(0).is_none());
+    zim.prefetch_clusters(&[0, 5, 0]);
+    assert!(zim.cluster_cache.get(0).is_some());
+
+    let uncached = Zim::new(&path).ok().unwrap();
+    uncached.prefetch_clusters(&[0]);
+    assert!(uncached.cluster_cache.get(0).is_none());
+}
+
+#[test]
+fn test_entry_cache() {
+    let path = test_zim::sample_zim("entry_cache");
+    let zim = Zim::open_with(&path, ZimOptions::new().entry_cache_size(2)).ok().unwrap();
+    let cached_keys = |zim: &Zim| zim.entry_cache.items.borrow().iter().map(|&(k, _)| k).collect::<Vec<u32>>();
+
+    // a hit gives back the same entry as reading it, and doesn't add a second copy
+    let foo = zim.get_by_url_index(3).unwrap();
+    assert_eq!(cached_keys(&zim), vec![3]);
+    let hit = zim.get_by_url_index(3).unwrap();
+    assert_eq!((&hit.url, &hit.title, &hit.target), (&foo.url, &foo.title, &foo.target));
+    assert_eq!(cached_keys(&zim), vec![3]);
+
+    // hits are served from the cache rather than the file
+    let mut planted = foo.clone();
+    planted.title = "Planted".to_owned();
+    zim.entry_cache.insert(3, &planted);
+    assert_eq!(zim.get_by_url_index(3).unwrap().title, "Planted");
+
+    // at capacity, the least recently used entry is evicted
+    zim.get_by_url_index(0).unwrap();
+    zim.get_by_url_index(3).unwrap();
+    zim.get_by_url_index(1).unwrap();
+    assert_eq!(cached_keys(&zim), vec![1, 3]);
+    assert_eq!(zim.get_by_url_index(0).unwrap().url, "Bar");
+    assert_eq!(cached_keys(&zim), vec![0, 1]);
+    assert_eq!(zim.get_by_url_index(3).unwrap().title, "Foo");
+
+    // entries that can't be read aren't cached
+    assert!(zim.get_by_url_index(100).is_none());
+    assert_eq!(cached_keys(&zim), vec![3, 0]);
+
+    // a size of 0 disables the cache
+    let uncached = Zim::open_with(&path, ZimOptions::new().entry_cache_size(0)).ok().unwrap();
+    for idx in 0..uncached.article_count {
+        uncached.get_by_url_index(idx).unwrap();
+    }
+    assert!(cached_keys(&uncached).is_empty());
+}
+
+#[test]
+fn test_on_decompress() {
+    use test_zim::{Content, TestEntry};
+    const CLUSTER: &[u8] = b"BZh91AY&SY\xe1y\x16\x8f\x00\x00\x03y\x80@@\x00 @\x00\x10\x00\x10 @\x10 \x001\x06L@\
+                             \xc2\x06\x89\xb0-!\xc7\x05C\x0c>.\xe4\x8ap\xa1!\xc2\xf2-\x1e";
+    let entries = vec![
+        TestEntry { namespace: 'A', url: "Foo", title: "Foo", revision: 0, content: Content::Data("text/plain", b"bzip2 bzip2 bzip2") },
+    ];
+    let path = test_zim::write_zim_compressed("on_decompress", entries, None, &|_| (3, CLUSTER.to_vec()));
+    let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let seen_by_hook = seen.clone();
+    let zim = Zim::open_with(&path, ZimOptions::new().on_decompress(move |info| {
+        seen_by_hook.lock().unwrap().push((info.cluster_idx, info.compressed_len, info.decompressed_len));
+    })).ok().unwrap();
+    zim.get_cluster(0).unwrap();
+    assert_eq!(*seen.lock().unwrap(), vec![(0, CLUSTER.len() as u64 + 1, 25)]);
+
+    // uncompressed clusters aren't reported
+    let seen_by_hook = seen.clone();
+    let zim = Zim::open_with(test_zim::sample_zim("on_decompress_none"), ZimOptions::new().on_decompress(move |info| {
+        seen_by_hook.lock().unwrap().push((info.cluster_idx, 0, 0));
+    })).ok().unwrap();
+    zim.get_cluster(0).unwrap();
+    assert_eq!(seen.lock().unwrap().len(), 1);
+}
+
+#[test]
+fn test_iterate_by_urls_ref() {
+    let zim = Zim::new(test_zim::sample_zim("iterate_by_urls_ref")).ok().unwrap();
+    let refs: Vec<DirectoryEntryRef> = zim.iterate_by_urls_ref().collect();
+    assert_eq!(refs.len(), zim.article_count as usize);
+    for (entry_ref, entry) in refs.iter().zip(zim.iterate_by_urls()) {
+        assert_eq!(entry_ref.sort_key(), entry.sort_key());
+        assert_eq!(entry_ref.title(), entry.title);
+        assert_eq!(entry_ref.mime_type(), entry.mime_type);
+        assert_eq!(entry_ref.revision(), entry.revision);
+        assert_eq!(entry_ref.target(), entry.target);
+        assert_eq!(entry_ref.encoded_len(), entry.encoded_len());
+        assert_eq!(entry_ref.to_entry().url, entry.url);
+    }
+    assert_eq!(refs[3].url(), "Foo");
+    assert_eq!(refs[2].url(), "Caf\u{e9}");
+    assert_eq!(zim.iterate_by_urls_ref().next_back().unwrap().url(), "Title");
+}
+
+#[test]
+fn test_well_known_main_page() {
+    use test_zim::{Content, TestEntry};
+    let mut entries = test_zim::sample_entries();
+    entries.push(TestEntry { namespace: 'W', url: "mainPage", title: "mainPage", revision: 0, content: Content::Redirect('A', "Bar") });
+
+    let zim = Zim::new(test_zim::write_zim("well_known", entries, None)).ok().unwrap();
+    assert_eq!(zim.well_known("mainPage").unwrap().target, Some(Target::Redirect(0)));
+    assert!(zim.well_known("favicon").is_none());
+    assert!(zim.main_page_idx.is_none());
+    assert_eq!(zim.main_page().unwrap().url, "mainPage");
+    assert_eq!(zim.main_page_content().unwrap().1, b"<p>bar</p>");
+
+    // the header's main page wins over W/mainPage
+    let mut entries = test_zim::sample_entries();
+    entries.push(TestEntry { namespace: 'W', url: "mainPage", title: "mainPage", revision: 0, content: Content::Redirect('A', "Bar") });
+    let zim = Zim::new(test_zim::write_zim("well_known_header", entries, Some(('A', "Foo")))).ok().unwrap();
+    assert_eq!(zim.main_page().unwrap().url, "Foo");
+
+    let zim = Zim::new(test_zim::write_zim("well_known_none", test_zim::sample_entries(), None)).ok().unwrap();
+    assert!(zim.main_page().is_none());
+}
+
+#[test]
+fn test_entry_info() {
+    let zim = Zim::new(test_zim::sample_zim("entry_info")).ok().unwrap();
+    let info = zim.entry_info(1).unwrap();
+    assert_eq!(info, EntryInfo { namespace: 'A', url: "Baz".to_owned(), title: "Baz".to_owned(), mime_id: 0xffff, is_redirect: true });
+    let info = zim.entry_info(4).unwrap();
+    assert_eq!((info.namespace, &info.url[..], info.is_redirect), ('I', "logo.png", false));
+    assert_eq!(zim.get_mimetype(info.mime_id), Some(MimeType::Type("image/png".to_owned())));
+    assert!(zim.entry_info(zim.article_count).is_none());
+}
+
+#[test]
+fn test_reopen() {
+    let zim = Zim::open_with(test_zim::sample_zim("reopen"), ZimOptions::new().buffered(true).cluster_cache_size(4)).ok().unwrap();
+    zim.get_cluster(0).unwrap();
+    let other = zim.reopen().ok().unwrap();
+    assert_eq!(other.article_count, zim.article_count);
+    assert_eq!(other.get_by_url('A', "Foo").unwrap().url, "Foo");
+    assert!(other.cluster_cache.get(0).is_none());
+    assert!(zim.cluster_cache.get(0).is_some());
+    match other.storage {
+        Storage::Buffered(_) => {}
+        _ => panic!("reopen should keep the options")
+    }
+}
+
+#[cfg(feature = "remote")]
+#[test]
+fn test_open_url() {
+    use test_zim::{Content, TestEntry};
+    // big enough that its cluster spans several of the pages small reads are fetched in
+    static BIG: [u8; 200000] = [b'x'; 200000];
+    let mut entries = test_zim::sample_entries();
+    entries.push(TestEntry { namespace: 'A', url: "Big", title: "Big", revision: 0, content: Content::Data("text/plain", &BIG) });
+    let path = test_zim::write_zim("open_url", entries, Some(('A', "Foo")));
+    let data = std::fs::read(&path).unwrap();
+    let local = Zim::new(&path).ok().unwrap();
+
+    for &lazy in &[false, true] {
+        let url = test_zim::serve(data.clone(), true);
+        let zim = Zim::open_url_with(&url, ZimOptions::new().lazy_tables(lazy)).ok().unwrap();
+        assert_eq!(zim.file_len(), data.len() as u64);
+        assert_eq!(zim.article_count, local.article_count);
+        assert_eq!(zim.main_page().unwrap().url, "Foo");
+        for entry in local.iterate_by_urls() {
+            let remote = zim.get_by_url(entry.namespace, &entry.url).unwrap();
+            assert_eq!(remote.title, entry.title);
+            if let Some(Target::Cluster(..)) = entry.target {
+                assert_eq!(zim.read_blob(&remote).ok().unwrap().data, local.read_blob(&entry).ok().unwrap().data);
+            }
+        }
+        assert_eq!(zim.read_blob(&zim.get_by_url('A', "Big").unwrap()).ok().unwrap().data.len(), BIG.len());
+        assert!(zim.checksum_matches());
+
+        let other = zim.reopen().ok().unwrap();
+        assert_eq!(other.get_by_url('A', "Foo").unwrap().url, "Foo");
+    }
+
+    // a server that ignores the Range header is reported, rather than downloaded from
+    let url = test_zim::serve(data, false);
+    let err = Zim::open_url(&url).err().unwrap();
+    assert_eq!(err.kind(), ParseErrorKind::Io);
+    assert!(err.to_string().contains("range requests"));
+}
+
+#[test]
+fn test_first_last_in_namespace() {
+    let zim = Zim::new(test_zim::sample_zim("first_in_namespace")).ok().unwrap();
+    for &ns in &['A', 'I', 'M'] {
+        let entries = zim.collect_namespace(ns);
+        assert_eq!(zim.first_in_namespace(ns).as_ref(), entries.first());
+        assert_eq!(zim.last_in_namespace(ns).as_ref(), entries.last());
+    }
+    assert_eq!(zim.first_in_namespace('A').unwrap().url, "Bar");
+    assert_eq!(zim.last_in_namespace('A').unwrap().url, "Foo");
+    assert_eq!(zim.first_in_namespace('I').unwrap().url, "logo.png");
+    // before, between, and after the namespaces in the archive
+    assert!(zim.first_in_namespace('-').is_none());
+    assert!(zim.first_in_namespace('B').is_none());
+    assert!(zim.last_in_namespace('B').is_none());
+    assert!(zim.last_in_namespace('X').is_none());
+}
+
+#[test]
+fn test_blob_offsets() {
+    let zim = Zim::new(test_zim::sample_zim("blob_offsets")).ok().unwrap();
+    let cluster = zim.get_cluster(0).unwrap();
+    assert_eq!(cluster.blob_offsets(), &[28, 38, 49, 59, 63, 74, 78][..]);
+    assert_eq!(*cluster.blob_offsets().last().unwrap() as usize, cluster.decompressed_len());
+    for idx in 0..6 {
+        assert_eq!(cluster.blob_size(idx), Some(cluster.get_blob(idx).len()));
+    }
+    assert_eq!(cluster.blob_size(6), None);
+    assert_eq!(cluster.blob_size(u32::MAX), None);
+}
+
+#[test]
+fn test_entries_without_content() {
+    use test_zim::{Content, TestEntry};
+    let mut entries = test_zim::sample_entries();
+    entries.push(TestEntry { namespace: 'A', url: "Gone", title: "Gone", revision: 0, content: Content::Deleted });
+    entries.push(TestEntry { namespace: 'A', url: "Link", title: "Link", revision: 0, content: Content::LinkTarget });
+    let zim = Zim::open_checked(test_zim::write_zim("without_content", entries, None)).ok().unwrap();
+
+    for &(url, ref mime) in &[("Gone", MimeType::DeletedEntry), ("Link", MimeType::LinkTarget)] {
+        let entry = zim.get_by_url('A', url).unwrap();
+        assert_eq!(entry.mime_type, *mime);
+        assert_eq!(entry.target, None);
+        assert_eq!(entry.title, url);
+        assert_eq!(zim.read_blob(&entry).err().unwrap().kind(), ParseErrorKind::NoContent);
+        let err = zim.write_article(&entry, true, &mut Vec::new()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(zim.resolve_entry(&entry).ok().unwrap().url, url);
+        #[cfg(feature = "sha2")]
+        assert!(zim.article_digest(&entry).is_none());
+    }
+    // iterating everything skips them cleanly
+    assert_eq!(zim.iterate_by_urls().count(), 9);
+    assert_eq!(zim.iter_article_content().count(), 6);
+    assert_eq!(zim.iterate_by_urls_ref().filter(|entry| entry.target().is_none()).count(), 2);
+}
+
+#[test]
+fn test_iter_grouped_by_cluster() {
+    let zim = Zim::new(test_zim::sample_zim("grouped_by_cluster")).ok().unwrap();
+    // everything is in one cluster, and the redirect doesn't split the group
+    let groups: Vec<_> = zim.iter_grouped_by_cluster().collect();
+    assert_eq!(groups.len(), 1);
+    let (cid, ref blobs) = groups[0];
+    assert_eq!(cid, 0);
+    let urls: Vec<&str> = blobs.iter().map(|(_, entry)| &entry.url[..]).collect();
+    assert_eq!(urls, vec!["Bar", "Caf\u{e9}", "Foo", "logo.png", "Counter", "Title"]);
+    assert!(blobs.iter().enumerate().all(|(i, &(bid, _))| bid == i as u32));
+
+    // point A/Foo at another cluster, which splits the entries around it into separate groups
+    use byteorder::{LittleEndian, WriteBytesExt};
+    let mut data = std::fs::read(test_zim::sample_zim("grouped_by_cluster_split")).unwrap();
+    let idx = zim.locate('A', "Foo").unwrap();
+    (&mut data[zim.url_offset(idx) as usize + 8..]).write_u32::<LittleEndian>(1).unwrap();
+    let zim = Zim::new(test_zim::write_file("grouped_by_cluster_split_changed", &data)).ok().unwrap();
+    let groups: Vec<(u32, usize)> = zim.iter_grouped_by_cluster().map(|(cid, blobs)| (cid, blobs.len())).collect();
+    assert_eq!(groups, vec![(0, 2), (1, 1), (0, 3)]);
+
+    let empty = Zim::new(test_zim::write_zim("grouped_by_cluster_empty", Vec::new(), None)).ok().unwrap();
+    assert!(empty.iter_grouped_by_cluster().next().is_none());
+}
+
+#[test]
+fn test_blob_count() {
+    use test_zim::{Content, TestEntry};
+    let zim = Zim::new(test_zim::sample_zim("blob_count")).ok().unwrap();
+    let cluster = zim.get_cluster(0).unwrap();
+    assert_eq!(cluster.blob_count(), 6);
+    assert_eq!(cluster.get_blob(cluster.blob_count() - 1), b"Test");
+    assert!(cluster.get_blob_checked(cluster.blob_count()).is_err());
+
+    // bytes after the last offset aren't part of the last blob
+    let entries = vec![
+        TestEntry { namespace: 'A', url: "Bar", title: "Bar", revision: 0, content: Content::Data("text/plain", b"bar") },
+        TestEntry { namespace: 'A', url: "Foo", title: "Foo", revision: 0, content: Content::Data("text/plain", b"foo") },
+    ];
+    let path = test_zim::write_zim_compressed("blob_count_padded", entries, None, &|cluster| {
+        let mut padded = cluster.to_vec();
+        padded.extend_from_slice(b"padding");
+        (1, padded)
+    });
+    let zim = Zim::new(path).ok().unwrap();
+    let cluster = zim.get_cluster(0).unwrap();
+    assert_eq!(cluster.blob_count(), 2);
+    assert_eq!(cluster.get_blob(1), b"foo");
+    assert_eq!(cluster.blob_size(1), Some(3));
+    let view = zim.cluster_view(0).unwrap();
+    assert_eq!(view.blob_count(), 2);
+    assert_eq!(view.get_blob(1), b"foo");
+    assert_eq!(zim.read_blob_partial(0, 1).ok().unwrap(), b"foo");
+}
+
+#[test]
+fn test_compression_ratio() {
+    let zim = Zim::new(test_zim::sample_zim("compression_ratio")).ok().unwrap();
+    assert_eq!(zim.get_cluster(0).unwrap().compression_ratio(), 1.0);
+    assert_eq!(zim.average_compression_ratio(1).ok().unwrap(), 1.0);
+
+    let zim = Zim::new(test_zim::write_zim("compression_ratio_empty", Vec::new(), None)).ok().unwrap();
+    assert_eq!(zim.average_compression_ratio(0).ok().unwrap(), 1.0);
+}
+
+#[test]
+fn test_read_by_url_index() {
+    use test_zim::{Content, TestEntry};
+    let mut entries = test_zim::sample_entries();
+    entries.push(TestEntry { namespace: 'A', url: "Qux", title: "Qux", revision: 0, content: Content::Redirect('A', "Baz") });
+    let zim = Zim::new(test_zim::write_zim("read_by_url_index", entries, None)).ok().unwrap();
+
+    let foo = zim.locate('A', "Foo").unwrap();
+    assert_eq!(zim.read_by_url_index(foo, false), Some((MimeType::from("text/html"), b"<p>foo</p>".to_vec())));
+    // A/Qux -> A/Baz -> A/Foo
+    let qux = zim.locate('A', "Qux").unwrap();
+    assert_eq!(zim.read_by_url_index(qux, true), Some((MimeType::from("text/html"), b"<p>foo</p>".to_vec())));
+    assert_eq!(zim.read_by_url_index(qux, false), None);
+    assert_eq!(zim.read_by_url_index(zim.article_count, true), None);
+}
+
+#[test]
+fn test_to_usize() {
+    assert_eq!(to_usize(4096).ok(), Some(4096));
+    let big = to_usize(1 << 32);
+    if cfg!(target_pointer_width = "32") {
+        let err = big.err().unwrap();
+        assert_eq!(err.kind(), ParseErrorKind::OutOfRange);
+        assert_eq!(err.offset(), Some(1 << 32));
+    } else {
+        assert_eq!(big.ok(), Some(1 << 32));
+    }
+}
+
+#[test]
+fn test_raw_entry() {
+    let zim = Zim::new(test_zim::sample_zim("raw_entry")).ok().unwrap();
+    let baz = zim.raw_entry(zim.locate('A', "Baz").unwrap()).unwrap();
+    assert_eq!(baz, &b"\xff\xff\x00A\x00\x00\x00\x00\x03\x00\x00\x00Baz\x00Baz\x00"[..]);
+    for idx in 0..zim.article_count {
+        let raw = zim.raw_entry(idx).unwrap();
+        assert_eq!(raw.len(), zim.get_by_url_index(idx).unwrap().encoded_len());
+        assert_eq!(raw.as_ptr() as usize - zim.region(0, 0).unwrap().as_ptr() as usize, zim.url_offset(idx) as usize);
+    }
+    assert!(zim.raw_entry(zim.article_count).is_none());
+}
+
+#[test]
+fn test_for_each_article_in_cluster_order() {
+    use test_zim::{Content, TestEntry};
+    // the cluster below, compressed with python's zlib module
+    const CLUSTER: &[u8] = b"x\xda\x13```\x10\x06b1 \x96\x06\xe2\xfc\xbc\xd4\x92\xf2\xfc\x92\x8c\xa2\xd4T\x00#9\x05\x09";
+    let entries = vec![
+        TestEntry { namespace: 'A', url: "1", title: "1", revision: 0, content: Content::Data("text/plain", b"one") },
+        TestEntry { namespace: 'A', url: "2", title: "2", revision: 0, content: Content::Data("text/plain", b"two") },
+        TestEntry { namespace: 'A', url: "3", title: "3", revision: 0, content: Content::Data("text/plain", b"three") },
+        TestEntry { namespace: 'A', url: "4", title: "4", revision: 0, content: Content::Redirect('A', "1") },
+    ];
+    let path = test_zim::write_zim_compressed("cluster_order", entries, None, &|cluster| {
+        assert_eq!(miniz_oxide::inflate::decompress_to_vec_zlib(CLUSTER).unwrap(), cluster);
+        (2, CLUSTER.to_vec())
+    });
+    let decompressed = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let counter = decompressed.clone();
+    let zim = Zim::open_with(path, ZimOptions::new().on_decompress(move |_| {
+        counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    })).ok().unwrap();
+
+    let mut seen = Vec::new();
+    zim.for_each_article_in_cluster_order(|entry, data| {
+        seen.push((entry.url.clone(), data.to_vec()));
+    }).ok().unwrap();
+    assert_eq!(seen, vec![("1".to_owned(), b"one".to_vec()), ("2".to_owned(), b"two".to_vec()),
+                          ("3".to_owned(), b"three".to_vec())]);
+    // the cluster was only decompressed once, for all three articles
+    assert_eq!(decompressed.load(std::sync::atomic::Ordering::Relaxed), 1);
+}
+
+#[test]
+#[cfg(windows)]
+fn test_drop_releases_file() {
+    let path = test_zim::sample_zim("drop_releases_file");
+    {
+        let zim = Zim::new(&path).ok().unwrap();
+        assert_eq!(zim.get_cluster(0).unwrap().get_blob(0), b"<p>bar</p>");
+    }
+    // a file that's still mapped can't be deleted on Windows
+    std::fs::remove_file(&path).unwrap();
+    assert!(!path.exists());
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_no_open_file_handle() {
+    let path = test_zim::sample_zim("no_open_file_handle");
+    let zim = Zim::new(&path).ok().unwrap();
+    // the mapping is all that's left; no file descriptor refers to the archive
+    let open_fds = std::fs::read_dir("/proc/self/fd").unwrap()
+        .filter_map(|fd| std::fs::read_link(fd.unwrap().path()).ok())
+        .filter(|target| *target == path)
+        .count();
+    assert_eq!(open_fds, 0);
+    assert_eq!(zim.get_cluster(0).unwrap().get_blob(0), b"<p>bar</p>");
+}
+
+#[test]
+fn test_resolve_relative() {
+    let zim = Zim::new(test_zim::sample_zim("resolve_relative")).ok().unwrap();
+    let foo = zim.get_by_url('A', "Foo").unwrap();
+    let resolve = |link| zim.resolve_relative(&foo, link).map(|entry| (entry.namespace, entry.url));
+
+    assert_eq!(resolve("Bar"), Some(('A', "Bar".to_owned())));
+    assert_eq!(resolve("./Bar#history"), Some(('A', "Bar".to_owned())));
+    assert_eq!(resolve("Caf%C3%A9?action=edit"), Some(('A', "Caf\u{e9}".to_owned())));
+    assert_eq!(resolve("../I/logo.png"), Some(('I', "logo.png".to_owned())));
+    assert_eq!(resolve("/M/Title"), Some(('M', "Title".to_owned())));
+    assert_eq!(resolve("../A/../M/./Counter"), Some(('M', "Counter".to_owned())));
+    assert_eq!(resolve("#top"), Some(('A', "Foo".to_owned())));
+    assert_eq!(resolve("../../A/Bar"), None);
+    assert_eq!(resolve("Missing"), None);
+    assert_eq!(resolve("https://example.com/A/Bar"), None);
+    assert_eq!(resolve("javascript:void(0)"), None);
+    assert_eq!(resolve("data:text/html,<p>foo</p>"), None);
+    assert_eq!(resolve("tel:+1-555-0100"), None);
+    assert_eq!(resolve("//A/Bar"), None);
+    assert_eq!(resolve("//host/x"), None);
+}
+
+#[test]
+fn test_has_scheme() {
+    for link in &["https://example.com", "mailto:someone@example.com", "javascript:void(0)", "data:,", "tel:123",
+                  "x-custom+v1.0:thing", "Bar:Baz/Qux"] {
+        assert!(has_scheme(link), "{} has a scheme", link);
+    }
+    for link in &["Bar", "./Category:Foo", "../A/Bar:Baz", "/A/Foo:Bar", ":Bar", "1abc:Bar", "a b:c", "#top:x"] {
+        assert!(!has_scheme(link), "{} has no scheme", link);
+    }
+}
+
+#[test]
+fn test_mimetype_is_text() {
+    for mime in &["text/html", "text/css", "text/plain; charset=utf-8", "Text/HTML", "application/json",
+                  "application/javascript", "application/ld+json", "image/svg+xml", "application/xml"] {
+        assert!(MimeType::from(*mime).is_text(), "{} should be text", mime);
+    }
+    for mime in &["image/png", "image/jpeg", "application/octet-stream", "application/pdf", "font/woff2", "video/webm"] {
+        assert!(!MimeType::from(*mime).is_text(), "{} should be binary", mime);
+    }
+    assert!(!MimeType::Redirect.is_text());
+    assert!(!MimeType::Unknown(7).is_text());
+}
+
+#[test]
+fn test_url_index() {
+    let zim = Zim::new(test_zim::sample_zim("url_index")).ok().unwrap();
+    let index = zim.build_url_index();
+    // a hit, a miss, and urls on either side of the A/I and I/M namespace boundaries
+    for &(namespace, url, found) in &[('A', "Bar", true), ('A', "Missing", false), ('A', "Foo", true),
+                                      ('I', "logo.png", true), ('A', "logo.png", false), ('I', "Foo", false),
+                                      ('M', "Counter", true), ('I', "Counter", false)] {
+        let indexed = zim.get_by_url_indexed(&index, namespace, url);
+        assert_eq!(indexed, zim.get_by_url(namespace, url), "{}/{}", namespace, url);
+        assert_eq!(indexed.is_some(), found, "{}/{}", namespace, url);
+        if let Some(entry) = indexed {
+            assert_eq!((entry.namespace, entry.url.as_str()), (namespace, url));
+        }
+    }
+}
+
+#[test]
+fn test_progress() {
+    // two blobs per cluster, so the six articles with data are spread over three clusters
+    let path = test_zim::write_zim_clustered("progress", test_zim::sample_entries(), 2, &|cluster| (1, cluster.to_vec()));
+    let zim = Zim::new(&path).ok().unwrap();
+    assert_eq!(zim.cluster_count, 3);
+    let expected = vec![(1, 3), (2, 3), (3, 3)];
+
+    let mut calls = Vec::new();
+    let mut articles = 0;
+    zim.for_each_article_with_progress(|_, _| articles += 1, |done, total| calls.push((done, total))).ok().unwrap();
+    assert_eq!(articles, 6);
+    assert_eq!(calls, expected);
+
+    let mut calls = Vec::new();
+    let stats = zim.size_stats_with_progress(|done, total| calls.push((done, total))).ok().unwrap();
+    assert_eq!(stats.get(&'A'), Some(&31));
+    assert_eq!(calls, expected);
+
+    // clusters with nothing to extract are still counted
+    let dir = std::env::temp_dir().join(format!("zim-test-{}-progress", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let mut calls = Vec::new();
+    zim.extract_to_with_progress(&dir, |entry| entry.namespace == 'M', |done, total| calls.push((done, total)))
+        .ok().unwrap();
+    assert_eq!(calls, expected);
+    assert_eq!(std::fs::read(dir.join("M/Title")).unwrap(), b"Test");
+    assert!(!dir.join("A/Foo").exists());
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_version() {
+    let path = test_zim::sample_zim("version");
+    let zim = Zim::new(&path).ok().unwrap();
+    assert_eq!(zim.version(), 5);
+    assert_eq!(zim.minor_version(), 0);
+
+    let mut data = std::fs::read(&path).unwrap();
+    data[4] = 6;
+    assert_eq!(Zim::new(test_zim::write_file("version_6", &data)).ok().unwrap().version(), 6);
+    data[4] = 7;
+    let err = Zim::new(test_zim::write_file("version_7", &data)).err().unwrap();
+    assert_eq!(err.kind(), ParseErrorKind::UnsupportedVersion);
+    assert_eq!(err.offset(), Some(4));
+    assert!(err.source().unwrap().to_string().contains("version 7"));
+}
+
+/// Writes the sample archive with two blobs per cluster, zlib-compressing the first cluster and
+/// leaving the others uncompressed, and returns its path along with each cluster as written
+/// (without the compression type byte)
+#[cfg(test)]
+fn mixed_clusters_zim(name: &str) -> (PathBuf, Vec<Vec<u8>>) {
+    let written = RefCell::new(Vec::new());
+    let path = test_zim::write_zim_clustered(name, test_zim::sample_entries(), 2, &|cluster| {
+        let (comp_type, data) = if written.borrow().is_empty() {
+            (2, miniz_oxide::deflate::compress_to_vec_zlib(cluster, 6))
+        } else {
+            (1, cluster.to_vec())
+        };
+        written.borrow_mut().push(data.clone());
+        (comp_type, data)
+    });
+    (path, written.into_inner())
+}
+
+#[test]
+fn test_raw_cluster_bytes() {
+    let (path, written) = mixed_clusters_zim("raw_cluster_bytes");
+    let zim = Zim::new(&path).ok().unwrap();
+    assert_eq!(zim.cluster_count, 3);
+    for (idx, &comp_type) in [2, 1, 1].iter().enumerate() {
+        let raw = zim.raw_cluster_bytes(idx as u32).unwrap();
+        assert_eq!(raw[0], comp_type);
+        assert_eq!(&raw[1..], &written[idx][..]);
+        assert_eq!(raw, zim.cluster_data(idx as u32).ok().unwrap());
+    }
+    assert!(zim.raw_cluster_bytes(3).is_none());
+}
+
+#[test]
+fn test_compression_types() {
+    let zim = Zim::new(test_zim::sample_zim("compression_types")).ok().unwrap();
+    assert!(zim.compression_types().into_iter().eq(Some(Compression::None)));
+    assert!(zim.uses_compression(Compression::None));
+    assert!(!zim.uses_compression(Compression::Zlib));
+
+    let (path, _) = mixed_clusters_zim("compression_types_mixed");
+    let zim = Zim::new(&path).ok().unwrap();
+    let expected: HashSet<Compression> = [Compression::None, Compression::Zlib].iter().cloned().collect();
+    assert_eq!(zim.compression_types(), expected);
+    assert!(zim.uses_compression(Compression::Zlib));
+    assert!(!zim.uses_compression(Compression::Xz));
+
+    // the high nibble (the extended cluster flag) doesn't change the compression
+    assert_eq!(Compression::from_byte(0x14), Compression::Xz);
+    assert_eq!(Compression::from_byte(0x11), Compression::None);
+    assert_eq!(Compression::from_byte(0), Compression::None);
+    assert_eq!(Compression::from_byte(2), Compression::Zlib);
+    assert_eq!(Compression::from_byte(3), Compression::Bzip2);
+    assert_eq!(Compression::from_byte(5), Compression::Zstd);
+    assert_eq!(Compression::from_byte(9), Compression::Unknown(9));
+}
+
+#[test]
+fn test_cluster_is_compressed() {
+    let (path, _) = mixed_clusters_zim("cluster_is_compressed");
+    let zim = Zim::new(&path).ok().unwrap();
+    let compressed = zim.get_cluster(0).unwrap();
+    assert!(compressed.is_compressed());
+    assert_eq!(compressed.compression(), Compression::Zlib);
+    assert_eq!(compressed.get_blob(0), b"<p>bar</p>");
+    for idx in 1..3 {
+        let cluster = zim.get_cluster(idx).unwrap();
+        assert!(!cluster.is_compressed());
+        assert_eq!(cluster.compression(), Compression::None);
+        assert_eq!(cluster.compression_ratio(), 1.0);
+    }
+}
+
+#[test]
+fn test_cluster_byte_range() {
+    let (path, _) = mixed_clusters_zim("cluster_byte_range");
+    let zim = Zim::new(&path).ok().unwrap();
+    let data = std::fs::read(&path).unwrap();
+    // the byte range of the cluster holding an entry's data, if it has any
+    let byte_range = |url: &str| {
+        match zim.get_by_url('A', url).unwrap().target {
+            Some(Target::Cluster(cid, _)) => zim.get_cluster(cid).map(|cluster| cluster.byte_range()),
+            _ => None
+        }
+    };
 
+    // A/Foo has the third blob, so its data starts the second cluster
+    let range = byte_range("Foo").unwrap();
+    let (start, end) = zim.cluster_range(1);
+    assert_eq!(range, start..end);
+    assert_eq!(&data[range.start as usize..range.end as usize], zim.raw_cluster_bytes(1).unwrap());
+    assert_eq!(range.end - range.start, zim.get_cluster(1).unwrap().compressed_len());
+    // clusters are laid out back to back, the last one ending at the checksum
+    assert_eq!(zim.get_cluster(0).unwrap().byte_range().end, range.start);
+    assert_eq!(zim.get_cluster(2).unwrap().byte_range().end, zim.data_end());
 
+    // A/Baz is a redirect, so it has no data and no cluster
+    assert_eq!(byte_range("Baz"), None);
 }
@@ -0,0 +1,36 @@
+//! XZ decompression through liblzma (with the `xz2` crate), used for clusters when the `liblzma`
+//! feature is enabled.
+
+use std::io::Read;
+use xz2::read::XzDecoder;
+
+/// Decompresses a complete .xz stream
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, &'static str> {
+    let mut out = Vec::new();
+    try!(decompress_into(data, &mut out));
+    Ok(out)
+}
+
+/// Decompresses a complete .xz stream into `out`, which is cleared first
+pub fn decompress_into(data: &[u8], out: &mut Vec<u8>) -> Result<(), &'static str> {
+    out.clear();
+    // clusters usually compress to around a quarter of their size
+    out.reserve(data.len() * 4);
+    match XzDecoder::new_multi_decoder(data).read_to_end(out) {
+        Ok(_) => Ok(()),
+        Err(_) => Err("Corrupt XZ data"),
+    }
+}
+
+#[test]
+fn test_liblzma() {
+    // "hello", compressed with xz
+    let data: Vec<u8> = vec!(
+        0xfd,0x37,0x7a,0x58,0x5a,0x00,0x00,0x04,0xe6,0xd6,0xb4,0x46,0x02,0x00,0x21,0x01,
+        0x16,0x00,0x00,0x00,0x74,0x2f,0xe5,0xa3,0x01,0x00,0x04,0x68,0x65,0x6c,0x6c,0x6f,
+        0x00,0x00,0x00,0x00,0xb1,0x37,0xb9,0xdb,0xe5,0xda,0x1e,0x9b,0x00,0x01,0x1d,0x05,
+        0xb8,0x2d,0x80,0xaf,0x1f,0xb6,0xf3,0x7d,0x01,0x00,0x00,0x00,0x00,0x04,0x59,0x5a
+    );
+    assert_eq!(decompress(&data).unwrap(), b"hello");
+    assert!(decompress(&data[..data.len() - 4]).is_err());
+}
@@ -0,0 +1,164 @@
+//! Reading archives over HTTP with range requests, used by `Zim::open_url` when the `remote`
+//! feature is enabled.
+
+use std::cell::RefCell;
+use std::cmp::min;
+use std::collections::HashMap;
+use std::io::Read;
+
+use super::{to_usize, ParseErrorKind, ParsingError};
+
+/// How much of the file is fetched at once for reads that fit in one page
+///
+/// Small reads (directory entries, table lookups) are served from whole pages, so reading the
+/// entries around one that was just read doesn't need another request.
+const PAGE_SIZE: usize = 64 * 1024;
+
+/// How much is read for a region whose length isn't known up front, like a directory entry
+const UNSIZED_READ_LEN: usize = 8 * 1024;
+
+/// Fetched bytes, keyed by the page or range of the file they came from
+type Fetched<K> = RefCell<HashMap<K, Box<[u8]>>>;
+
+/// An archive on a web server, fetched a range at a time as it's read
+///
+/// Everything fetched is kept until the storage is dropped, since the rest of the crate borrows
+/// slices of the file for as long as the `Zim` lives.  Reads that fit in one page go through a
+/// page cache; larger ones (clusters, mostly) are fetched exactly and cached by their range, so
+/// reading a cluster again doesn't fetch it again.
+pub struct RemoteStorage {
+    pub url: String,
+    pub len: usize,
+    agent: ureq::Agent,
+    // fetched data is boxed and never removed or replaced, so the bytes don't move until the
+    // storage is dropped; see `stored`
+    pages: Fetched<usize>,
+    ranges: Fetched<(usize, usize)>,
+}
+
+impl RemoteStorage {
+    /// Starts reading the archive at the given url, fetching its first page
+    ///
+    /// Fails if the server doesn't answer a range request with a partial response.
+    pub fn open(url: &str) -> Result<RemoteStorage, ParsingError> {
+        let agent = ureq::AgentBuilder::new().build();
+        // the first page doubles as the request that finds out how long the file is
+        let (data, len) = try!(fetch(&agent, url, 0, PAGE_SIZE));
+        let storage = RemoteStorage {
+            url: url.to_owned(),
+            len: len,
+            agent: agent,
+            pages: RefCell::new(HashMap::new()),
+            ranges: RefCell::new(HashMap::new()),
+        };
+        storage.pages.borrow_mut().insert(0, data.into_boxed_slice());
+        Ok(storage)
+    }
+
+    /// Returns up to `len` bytes of the file starting at `off`, fetching them if needed
+    ///
+    /// Like `Storage::region`, the range is clamped to the end of the file.  A `len` of
+    /// `usize::MAX` means the caller doesn't know how much it needs, and reads
+    /// `UNSIZED_READ_LEN` bytes rather than the rest of the file.
+    pub fn region(&self, off: usize, len: usize) -> Result<&[u8], ParsingError> {
+        let len = if len == usize::MAX { UNSIZED_READ_LEN } else { len };
+        let start = min(off, self.len);
+        let end = min(start.saturating_add(len), self.len);
+        if start == end {
+            return Ok(&[]);
+        }
+
+        let page = start / PAGE_SIZE;
+        if (end - 1) / PAGE_SIZE == page {
+            let data = try!(self.page(page));
+            // a short page means the file shrank, which reads like a truncated file
+            let base = page * PAGE_SIZE;
+            return Ok(&data[min(start - base, data.len())..min(end - base, data.len())]);
+        }
+
+        if let Some(data) = self.ranges.borrow().get(&(start, end)) {
+            return Ok(self.stored(data));
+        }
+        let (data, _) = try!(fetch(&self.agent, &self.url, start, end));
+        let mut ranges = self.ranges.borrow_mut();
+        Ok(self.stored(ranges.entry((start, end)).or_insert(data.into_boxed_slice())))
+    }
+
+    /// Returns the given page of the file, fetching it if needed
+    fn page(&self, page: usize) -> Result<&[u8], ParsingError> {
+        if let Some(data) = self.pages.borrow().get(&page) {
+            return Ok(self.stored(data));
+        }
+        let start = page * PAGE_SIZE;
+        let (data, _) = try!(fetch(&self.agent, &self.url, start, min(start + PAGE_SIZE, self.len)));
+        let mut pages = self.pages.borrow_mut();
+        Ok(self.stored(pages.entry(page).or_insert(data.into_boxed_slice())))
+    }
+
+    /// Extends the borrow of fetched data from the cache's `RefCell` to the storage itself
+    fn stored<'a>(&'a self, data: &[u8]) -> &'a [u8] {
+        // Fetched data is only ever added to the caches, in its own allocation, so it stays put
+        // and alive for as long as the storage does, whatever else is added in the meantime.
+        unsafe { &*(data as *const [u8]) }
+    }
+}
+
+/// Fetches bytes `start..end` of the file, returning them along with the length of the file
+///
+/// The server may send fewer bytes than asked for, if the file is shorter.
+fn fetch(agent: &ureq::Agent, url: &str, start: usize, end: usize) -> Result<(Vec<u8>, usize), ParsingError> {
+    let range = format!("bytes={}-{}", start, end - 1);
+    let response = match agent.get(url).set("Range", &range).call() {
+        Ok(response) => response,
+        Err(e) => return Err(ParsingError {
+            cause: Some(Box::new(e)),
+            ..ParsingError::new(ParseErrorKind::Io, "HTTP request failed").at_offset(start as u64)
+        })
+    };
+    if response.status() != 206 {
+        return Err(ParsingError {
+            cause: Some(From::from(format!("got status {} for a range request", response.status()))),
+            ..ParsingError::new(ParseErrorKind::Io, "Server doesn't support range requests")
+        });
+    }
+    let (first, len) = match response.header("Content-Range").and_then(parse_content_range) {
+        Some(range) => range,
+        None => return Err(ParsingError::new(ParseErrorKind::Io, "Server sent a partial response without a usable Content-Range"))
+    };
+    if first != start as u64 {
+        return Err(ParsingError::new(ParseErrorKind::Io, "Server sent a different range than the one asked for")
+                   .at_offset(start as u64));
+    }
+    let len = try!(to_usize(len));
+
+    let mut data = Vec::with_capacity(end - start);
+    try!(response.into_reader().take((end - start) as u64).read_to_end(&mut data));
+    Ok((data, len))
+}
+
+/// Parses the first byte and the total length out of a `Content-Range` header
+///
+/// The header looks like `bytes 0-79/123456`.  A total of `*` (unknown) isn't usable, since
+/// reads are clamped to the length of the file.
+fn parse_content_range(header: &str) -> Option<(u64, u64)> {
+    let rest = match header.trim().strip_prefix("bytes ") {
+        Some(rest) => rest.trim(),
+        None => return None
+    };
+    let mut parts = rest.splitn(2, '/');
+    let first = parts.next().and_then(|range| range.split('-').next()).and_then(|first| first.parse().ok());
+    let len = parts.next().and_then(|len| len.parse().ok());
+    match (first, len) {
+        (Some(first), Some(len)) => Some((first, len)),
+        _ => None
+    }
+}
+
+#[test]
+fn test_parse_content_range() {
+    assert_eq!(parse_content_range("bytes 0-79/123456"), Some((0, 123456)));
+    assert_eq!(parse_content_range("bytes 100-199/200"), Some((100, 200)));
+    assert_eq!(parse_content_range("bytes 0-79/*"), None);
+    assert_eq!(parse_content_range("bytes */200"), None);
+    assert_eq!(parse_content_range("items 0-1/2"), None);
+}
@@ -0,0 +1,295 @@
+//! Builds small ZIM files for the tests to read.
+//!
+//! All articles go into a single cluster, which is uncompressed unless the test compresses it
+//! with `write_zim_compressed`; `write_zim_clustered` spreads them over several.
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use std::io::Write;
+use std::path::PathBuf;
+use md5::{Digest, Md5};
+
+/// Compresses a cluster, returning the compression type byte along with the compressed data
+pub type Compress<'a> = dyn Fn(&[u8]) -> (u8, Vec<u8>) + 'a;
+
+/// What a test entry holds
+pub enum Content {
+    /// A mimetype and the article data
+    Data(&'static str, &'static [u8]),
+    /// A redirect to the given namespace and url
+    Redirect(char, &'static str),
+    /// A link target entry, which has no data
+    LinkTarget,
+    /// A deleted entry, which has no data
+    Deleted,
+}
+
+pub struct TestEntry {
+    pub namespace: char,
+    pub url: &'static str,
+    pub title: &'static str,
+    pub revision: u32,
+    pub content: Content,
+}
+
+/// The entries used by `sample_zim`
+pub fn sample_entries() -> Vec<TestEntry> {
+    vec![
+        TestEntry { namespace: 'A', url: "Foo", title: "Foo", revision: 0, content: Content::Data("text/html", b"<p>foo</p>") },
+        TestEntry { namespace: 'A', url: "Bar", title: "Bar", revision: 0, content: Content::Data("text/html", b"<p>bar</p>") },
+        TestEntry { namespace: 'A', url: "Baz", title: "Baz", revision: 0, content: Content::Redirect('A', "Foo") },
+        TestEntry { namespace: 'A', url: "Caf\u{e9}", title: "Caf\u{e9}", revision: 0, content: Content::Data("text/html", b"<p>cafe</p>") },
+        TestEntry { namespace: 'I', url: "logo.png", title: "logo.png", revision: 0, content: Content::Data("image/png", b"\x89PNG") },
+        TestEntry { namespace: 'M', url: "Counter", title: "Counter", revision: 3, content: Content::Data("text/plain", b"text/html=3") },
+        TestEntry { namespace: 'M', url: "Title", title: "Title", revision: 0, content: Content::Data("text/plain", b"Test") },
+    ]
+}
+
+/// Writes the sample archive to a temporary file called `name` and returns its path
+///
+/// Each test should use its own `name`, since tests run in parallel.
+pub fn sample_zim(name: &str) -> PathBuf {
+    write_zim(name, sample_entries(), Some(('A', "Foo")))
+}
+
+/// Writes a ZIM file with the given entries to a temporary file called `name`
+///
+/// If none of the entries have any data, the file won't have any clusters.
+pub fn write_zim(name: &str, entries: Vec<TestEntry>, main_page: Option<(char, &str)>) -> PathBuf {
+    write_zim_compressed(name, entries, main_page, &|cluster| (1, cluster.to_vec()))
+}
+
+/// Like `write_zim`, but passes the cluster through `compress` first
+///
+/// `compress` is given the uncompressed cluster data, and returns the compression type byte along
+/// with the compressed data.
+pub fn write_zim_compressed(name: &str, entries: Vec<TestEntry>, main_page: Option<(char, &str)>,
+                            compress: &Compress<'_>) -> PathBuf {
+    write_zim_with_gap(name, entries, main_page, compress, 0, usize::MAX)
+}
+
+/// Like `write_zim_compressed`, but starts a new cluster after every `blobs_per_cluster` blobs
+///
+/// Blobs are assigned to clusters in url order, and `compress` is called for each cluster.
+pub fn write_zim_clustered(name: &str, entries: Vec<TestEntry>, blobs_per_cluster: usize,
+                           compress: &Compress<'_>) -> PathBuf {
+    write_zim_with_gap(name, entries, None, compress, 0, blobs_per_cluster)
+}
+
+/// Like `write_zim`, but leaves `gap` bytes of padding between the header and the mime list
+pub fn write_zim_with_mime_gap(name: &str, entries: Vec<TestEntry>, gap: usize) -> PathBuf {
+    write_zim_with_gap(name, entries, None, &|cluster| (1, cluster.to_vec()), gap, usize::MAX)
+}
+
+fn write_zim_with_gap(name: &str, mut entries: Vec<TestEntry>, main_page: Option<(char, &str)>,
+                      compress: &Compress<'_>, gap: usize, blobs_per_cluster: usize) -> PathBuf {
+    entries.sort_by(|a, b| (a.namespace, a.url).cmp(&(b.namespace, b.url)));
+    let url_idx = |ns: char, url: &str| {
+        entries.iter().position(|e| e.namespace == ns && e.url == url).unwrap() as u32
+    };
+
+    let mut mimes: Vec<&str> = Vec::new();
+    for entry in &entries {
+        if let Content::Data(mime, _) = entry.content {
+            if !mimes.contains(&mime) {
+                mimes.push(mime);
+            }
+        }
+    }
+
+    let mut mime_list = Vec::new();
+    for mime in &mimes {
+        mime_list.extend_from_slice(mime.as_bytes());
+        mime_list.push(0);
+    }
+    mime_list.push(0);
+
+    // the clusters, and the directory entries pointing into them
+    let mut blobs: Vec<&[u8]> = Vec::new();
+    let mut dir_entries = Vec::new();
+    for entry in &entries {
+        let mut d = Vec::new();
+        match entry.content {
+            Content::Data(mime, data) => {
+                d.write_u16::<LittleEndian>(mimes.iter().position(|m| *m == mime).unwrap() as u16).unwrap();
+                d.push(0);
+                d.push(entry.namespace as u8);
+                d.write_u32::<LittleEndian>(entry.revision).unwrap();
+                d.write_u32::<LittleEndian>((blobs.len() / blobs_per_cluster) as u32).unwrap();
+                d.write_u32::<LittleEndian>((blobs.len() % blobs_per_cluster) as u32).unwrap();
+                blobs.push(data);
+            }
+            Content::Redirect(ns, url) => {
+                d.write_u16::<LittleEndian>(0xffff).unwrap();
+                d.push(0);
+                d.push(entry.namespace as u8);
+                d.write_u32::<LittleEndian>(entry.revision).unwrap();
+                d.write_u32::<LittleEndian>(url_idx(ns, url)).unwrap();
+            }
+            Content::LinkTarget | Content::Deleted => {
+                let mime_id = if let Content::LinkTarget = entry.content { 0xfffe } else { 0xfffd };
+                d.write_u16::<LittleEndian>(mime_id).unwrap();
+                d.push(0);
+                d.push(entry.namespace as u8);
+                d.write_u32::<LittleEndian>(entry.revision).unwrap();
+            }
+        }
+        d.extend_from_slice(entry.url.as_bytes());
+        d.push(0);
+        d.extend_from_slice(entry.title.as_bytes());
+        d.push(0);
+        dir_entries.push(d);
+    }
+
+    let mut clusters = Vec::new();
+    for cluster_blobs in blobs.chunks(blobs_per_cluster) {
+        let mut cluster = Vec::new();
+        let mut off = (cluster_blobs.len() as u32 + 1) * 4;
+        for blob in cluster_blobs {
+            cluster.write_u32::<LittleEndian>(off).unwrap();
+            off += blob.len() as u32;
+        }
+        cluster.write_u32::<LittleEndian>(off).unwrap();
+        for blob in cluster_blobs {
+            cluster.extend_from_slice(blob);
+        }
+        let (comp_type, compressed) = compress(&cluster);
+        let mut cluster = vec![comp_type];
+        cluster.extend(compressed);
+        clusters.push(cluster);
+    }
+    let cluster_count = clusters.len() as u64;
+
+    // an empty title means the url is used as the title, including for sorting
+    let title = |e: &TestEntry| if e.title.is_empty() { e.url } else { e.title };
+    let mut title_order: Vec<u32> = (0..entries.len() as u32).collect();
+    title_order.sort_by(|&a, &b| {
+        let (a, b) = (&entries[a as usize], &entries[b as usize]);
+        (a.namespace, title(a)).cmp(&(b.namespace, title(b)))
+    });
+
+    let article_count = entries.len() as u64;
+    let mime_list_pos = 80 + gap as u64;
+    let url_ptr_pos = mime_list_pos + mime_list.len() as u64;
+    let title_ptr_pos = url_ptr_pos + article_count * 8;
+    let mut dir_pos = title_ptr_pos + article_count * 4;
+    let mut dir_offsets = Vec::new();
+    for d in &dir_entries {
+        dir_offsets.push(dir_pos);
+        dir_pos += d.len() as u64;
+    }
+    let cluster_ptr_pos = dir_pos;
+    let mut cluster_pos = cluster_ptr_pos + cluster_count * 8;
+    let mut cluster_offsets = Vec::new();
+    for cluster in &clusters {
+        cluster_offsets.push(cluster_pos);
+        cluster_pos += cluster.len() as u64;
+    }
+    let checksum_pos = cluster_pos;
+
+    let mut f = Vec::new();
+    f.write_u32::<LittleEndian>(72173914).unwrap();
+    f.write_u16::<LittleEndian>(5).unwrap();
+    f.write_u16::<LittleEndian>(0).unwrap();
+    f.extend_from_slice(&[0x42; 16]);
+    f.write_u32::<LittleEndian>(article_count as u32).unwrap();
+    f.write_u32::<LittleEndian>(cluster_count as u32).unwrap();
+    f.write_u64::<LittleEndian>(url_ptr_pos).unwrap();
+    f.write_u64::<LittleEndian>(title_ptr_pos).unwrap();
+    f.write_u64::<LittleEndian>(cluster_ptr_pos).unwrap();
+    f.write_u64::<LittleEndian>(mime_list_pos).unwrap();
+    f.write_u32::<LittleEndian>(main_page.map_or(0xffffffff, |(ns, url)| url_idx(ns, url))).unwrap();
+    f.write_u32::<LittleEndian>(0xffffffff).unwrap();
+    f.write_u64::<LittleEndian>(checksum_pos).unwrap();
+    f.extend(std::iter::repeat_n(0xff, gap));
+    f.extend_from_slice(&mime_list);
+    for off in &dir_offsets {
+        f.write_u64::<LittleEndian>(*off).unwrap();
+    }
+    for idx in &title_order {
+        f.write_u32::<LittleEndian>(*idx).unwrap();
+    }
+    for d in &dir_entries {
+        f.extend_from_slice(d);
+    }
+    for off in &cluster_offsets {
+        f.write_u64::<LittleEndian>(*off).unwrap();
+    }
+    for cluster in &clusters {
+        f.extend_from_slice(cluster);
+    }
+    assert_eq!(f.len() as u64, checksum_pos);
+
+    let mut hasher = Md5::new();
+    hasher.update(&f);
+    f.extend_from_slice(&hasher.finalize());
+
+    write_file(name, &f)
+}
+
+/// Writes arbitrary bytes to a temporary file called `name`, for tests of malformed archives
+pub fn write_file(name: &str, data: &[u8]) -> PathBuf {
+    let path = ::std::env::temp_dir().join(format!("zim-test-{}-{}.zim", ::std::process::id(), name));
+    ::std::fs::File::create(&path).unwrap().write_all(data).unwrap();
+    path
+}
+
+/// Serves `data` over HTTP on a local port, returning its url
+///
+/// If `ranges` is false, the server ignores `Range` headers and always sends the whole file, like
+/// a server without range support.  The server runs until the test process exits.
+#[cfg(feature = "remote")]
+pub fn serve(data: Vec<u8>, ranges: bool) -> String {
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/test.zim", listener.local_addr().unwrap());
+    let data = Arc::new(data);
+    ::std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let (stream, data) = (stream.unwrap(), data.clone());
+            ::std::thread::spawn(move || {
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut stream = stream;
+                // answer requests on this connection until the client closes it
+                loop {
+                    let mut range = None;
+                    let mut line = String::new();
+                    if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                        return;
+                    }
+                    loop {
+                        line.clear();
+                        reader.read_line(&mut line).unwrap();
+                        let header = line.trim().to_ascii_lowercase();
+                        if header.is_empty() {
+                            break;
+                        }
+                        if let Some(bytes) = header.strip_prefix("range: bytes=") {
+                            let mut parts = bytes.splitn(2, '-');
+                            let start: usize = parts.next().unwrap().parse().unwrap();
+                            let end: usize = parts.next().unwrap().parse().unwrap();
+                            range = Some((start, ::std::cmp::min(end + 1, data.len())));
+                        }
+                    }
+                    let head = match range {
+                        Some((start, end)) if ranges => {
+                            format!("HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\nContent-Length: {}\r\n\r\n",
+                                    start, end - 1, data.len(), end - start)
+                        }
+                        _ => format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", data.len())
+                    };
+                    let body = match range {
+                        Some((start, end)) if ranges => &data[start..end],
+                        _ => &data[..]
+                    };
+                    if stream.write_all(head.as_bytes()).and_then(|_| stream.write_all(body)).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+    });
+    url
+}